@@ -0,0 +1,183 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::{
+    physics::PuckPhysics,
+    player::Team,
+    prediction::PuckPredictor,
+    puck::Puck,
+    simulation::{SimulationClock, SimulationRng},
+    stick::Stick,
+    table::TableGeometry,
+    zone::{restrict_to_half, PlayerZone, ZoneSettings},
+};
+
+/// Seconds of the puck's path considered when defending
+const PREDICTION_HORIZON: f32 = 1.5;
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    /// Seconds between two looks at the puck
+    fn reaction_time(self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.35,
+            Difficulty::Medium => 0.2,
+            Difficulty::Hard => 0.08,
+        }
+    }
+
+    /// Stick speed in pixels per second
+    fn max_speed(self) -> f32 {
+        match self {
+            Difficulty::Easy => 600.0,
+            Difficulty::Medium => 1000.0,
+            Difficulty::Hard => 1600.0,
+        }
+    }
+
+    /// Largest offset in pixels of the aimed point from the intended one
+    fn aim_error(self) -> f32 {
+        match self {
+            Difficulty::Easy => 120.0,
+            Difficulty::Medium => 60.0,
+            Difficulty::Hard => 20.0,
+        }
+    }
+}
+
+/// Drives a stick instead of a lidar track
+#[derive(Component)]
+pub struct ComputerPlayer {
+    pub difficulty: Difficulty,
+    target: Option<Vec2>,
+    next_decision: f64,
+}
+
+impl ComputerPlayer {
+    pub fn new(difficulty: Difficulty) -> Self {
+        Self {
+            difficulty,
+            target: None,
+            next_decision: 0.0,
+        }
+    }
+}
+
+fn choose_target(
+    team: Team,
+    stick: Vec2,
+    puck: Vec2,
+    puck_velocity: Vec2,
+    predictor: &PuckPredictor,
+    geometry: &TableGeometry,
+) -> Vec2 {
+    let side = team.side();
+    let stick_radius = geometry.stick_diameter / 2.0;
+    let home = geometry.goal_center(team) - Vec2::new(side * geometry.stick_diameter, 0.0);
+    let goal_half_width = geometry.goal_width / 2.0;
+
+    let is_incoming = puck_velocity.x * side > 0.0;
+    let is_on_own_half = puck.x * side > 0.0;
+    if is_incoming && puck_velocity.length() > 300.0 {
+        // Defend: block the puck where it will cross in front of the goal
+        let y = predictor
+            .predict(puck, puck_velocity, PREDICTION_HORIZON)
+            .crossing(home.x)
+            .map_or(puck.y, |(_, crossing)| crossing.y)
+            .clamp(
+                home.y - goal_half_width - stick_radius,
+                home.y + goal_half_width + stick_radius,
+            );
+        Vec2::new(home.x, y)
+    } else if is_on_own_half {
+        // Attack: get behind the puck as seen from the opponent's goal, then push through it
+        let opponent_goal = geometry.goal_center(team.opponent());
+        let direction = (opponent_goal - puck).normalize_or_zero();
+        let is_behind_puck = (stick - puck).dot(direction) < 0.0;
+        if is_behind_puck {
+            puck + direction * stick_radius
+        } else {
+            puck - direction * (stick_radius + geometry.puck_diameter)
+        }
+    } else {
+        Vec2::new(
+            home.x,
+            puck.y
+                .clamp(home.y - goal_half_width, home.y + goal_half_width),
+        )
+    }
+}
+
+pub fn drive_computer_players(
+    clock: Res<SimulationClock>,
+    geometry: Res<TableGeometry>,
+    physics: Res<PuckPhysics>,
+    zone_settings: Res<ZoneSettings>,
+    mut rng: ResMut<SimulationRng>,
+    pucks: Query<(&Transform, &Velocity), With<Puck>>,
+    mut sticks: Query<
+        (&mut Transform, &mut ComputerPlayer, &PlayerZone, &Team),
+        (With<Stick>, Without<Puck>),
+    >,
+) {
+    let now = clock.seconds_since_startup();
+    let predictor = PuckPredictor::new(&geometry, &physics);
+    for (mut transform, mut computer, zone, &team) in &mut sticks {
+        let stick = transform.translation.truncate();
+        if now >= computer.next_decision {
+            computer.next_decision = now + computer.difficulty.reaction_time();
+            // Pucks on the own half are the most urgent, then the closest one
+            let puck = pucks.iter().min_by(|(left, _), (right, _)| {
+                let urgency = |transform: &Transform| {
+                    let position = transform.translation.truncate();
+                    let own_half_bonus = if position.x * team.side() > 0.0 {
+                        0.0
+                    } else {
+                        geometry.length
+                    };
+                    position.distance(stick) + own_half_bonus
+                };
+                urgency(left).total_cmp(&urgency(right))
+            });
+            computer.target = puck.map(|(puck, velocity)| {
+                let aim_error = computer.difficulty.aim_error();
+                let error = Vec2::new(
+                    rng.0.gen_range(-aim_error..=aim_error),
+                    rng.0.gen_range(-aim_error..=aim_error),
+                );
+                choose_target(
+                    team,
+                    stick,
+                    puck.translation.truncate(),
+                    velocity.linvel,
+                    &predictor,
+                    &geometry,
+                ) + error
+            });
+        }
+
+        let target = match computer.target {
+            Some(target) => target,
+            None => continue,
+        };
+        let max_step = computer.difficulty.max_speed() * clock.delta_seconds();
+        let mut position = stick + (target - stick).clamp_length_max(max_step);
+        let radius = geometry.stick_diameter / 2.0 * transform.scale.x;
+        position = geometry.clamp_inside(position, radius);
+        if zone_settings.restrict_to_half {
+            position = restrict_to_half(position, team, &geometry);
+        }
+        // A target outside the zone is approached up to the zone's edge
+        position = zone.limit_step(stick, position);
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
+}
@@ -4,7 +4,11 @@ pub enum AppState {
     Setup,
     ConnectingToLidar,
     Calibration,
+    Lobby,
     Game(GameState),
+    /// Slow-motion replay of the last goal
+    Replay,
+    MatchOver,
     Tracker,
 }
 
@@ -0,0 +1,267 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    assets::{Arenas, Fonts, Textures},
+    physics::{surface_restitution, PuckPhysics},
+    rules::LobbyUi,
+    table::{spawn_table, TableEntity, TableGeometry, Wall},
+};
+
+/// Table layout loaded from an `.arena.ron` file in `assets/arenas`
+///
+/// Everything left out keeps the value of the table from the config. The puck predictor and the
+/// computer players only know about the rails and goals, not about bumpers and obstacles.
+#[derive(Debug, Deserialize, TypeUuid)]
+#[uuid = "5f0c7a4e-2b1d-4c8e-9a63-1e7d2f4b8c90"]
+pub struct Arena {
+    pub name: String,
+    #[serde(default)]
+    pub goal_width: Option<f32>,
+    /// Vertical position of the center of both goal mouths
+    #[serde(default)]
+    pub goal_offset: Option<f32>,
+    /// Polylines replacing the two default rails
+    #[serde(default)]
+    pub rails: Option<Vec<Vec<[f32; 2]>>>,
+    #[serde(default)]
+    pub bumpers: Vec<Bumper>,
+    #[serde(default)]
+    pub rotating_obstacles: Vec<RotatingObstacle>,
+    /// Path of the table surface texture relative to `assets`
+    #[serde(default)]
+    pub table_texture: Option<String>,
+}
+
+/// Round, fixed obstacle bouncing the puck off with its own restitution
+#[derive(Clone, Debug, Deserialize, Component)]
+pub struct Bumper {
+    pub position: [f32; 2],
+    pub diameter: f32,
+    pub restitution: f32,
+}
+
+/// Bar spinning around its center, treated like a wall
+#[derive(Clone, Debug, Deserialize, Component)]
+pub struct RotatingObstacle {
+    pub position: [f32; 2],
+    pub length: f32,
+    pub thickness: f32,
+    /// Radians per second, positive is counterclockwise
+    pub angular_velocity: f32,
+}
+
+impl Arena {
+    /// Geometry of the config table changed by this arena
+    pub fn geometry(&self, base: &TableGeometry) -> TableGeometry {
+        TableGeometry {
+            goal_width: self.goal_width.unwrap_or(base.goal_width),
+            goal_offset: self.goal_offset.unwrap_or(base.goal_offset),
+            rails: self.rails.clone().or_else(|| base.rails.clone()),
+            ..base.clone()
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ArenaLoader;
+
+impl AssetLoader for ArenaLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let arena: Arena = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(arena));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["arena.ron"]
+    }
+}
+
+/// Arena chosen in the lobby, `None` is the plain table from the config
+pub struct ArenaSelection {
+    pub arena: Option<Handle<Arena>>,
+    /// Table from the config that arenas are applied to
+    base_geometry: TableGeometry,
+}
+
+impl ArenaSelection {
+    pub fn new(base_geometry: TableGeometry) -> Self {
+        Self {
+            arena: None,
+            base_geometry,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct ArenaLabel;
+
+pub fn setup_arena_label(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    selection: Res<ArenaSelection>,
+    arenas: Res<Assets<Arena>>,
+) {
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                arena_label(&selection, &arenas),
+                TextStyle {
+                    font: fonts.arial.clone(),
+                    font_size: 60.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_alignment(TextAlignment::CENTER),
+            transform: Transform::from_xyz(0.0, -250.0, 20.0),
+            ..default()
+        })
+        .insert(ArenaLabel)
+        .insert(LobbyUi);
+}
+
+fn arena_label(selection: &ArenaSelection, arenas: &Assets<Arena>) -> String {
+    let name = selection
+        .arena
+        .as_ref()
+        .and_then(|handle| arenas.get(handle))
+        .map_or("Standard table", |arena| arena.name.as_str());
+    format!("< Arena: {} >", name)
+}
+
+/// Cycles through the loaded arenas with the arrow keys and rebuilds the table
+pub fn select_arena(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    textures: Res<Textures>,
+    physics: Res<PuckPhysics>,
+    arena_folder: Res<Arenas>,
+    arenas: Res<Assets<Arena>>,
+    mut selection: ResMut<ArenaSelection>,
+    mut geometry: ResMut<TableGeometry>,
+    table_entities: Query<Entity, With<TableEntity>>,
+    mut labels: Query<&mut Text, With<ArenaLabel>>,
+) {
+    let step: isize = if keyboard_input.just_pressed(KeyCode::Right) {
+        1
+    } else if keyboard_input.just_pressed(KeyCode::Left) {
+        -1
+    } else {
+        return;
+    };
+
+    // Files in the folder that are not arenas never load as one and are skipped
+    let mut handles: Vec<Handle<Arena>> = arena_folder
+        .folder
+        .iter()
+        .map(|handle| handle.clone().typed::<Arena>())
+        .filter(|handle| arenas.get(handle).is_some())
+        .collect();
+    handles.sort_by(|left, right| {
+        let name = |handle: &Handle<Arena>| arenas.get(handle).map(|arena| arena.name.clone());
+        name(left).cmp(&name(right))
+    });
+
+    // Index 0 is the plain table, followed by the arenas
+    let choices = handles.len() as isize + 1;
+    let current = selection
+        .arena
+        .as_ref()
+        .and_then(|selected| handles.iter().position(|handle| handle == selected))
+        .map_or(0, |index| index as isize + 1);
+    let next = (current + step).rem_euclid(choices) as usize;
+    selection.arena = next.checked_sub(1).map(|index| handles[index].clone());
+
+    let arena = selection
+        .arena
+        .as_ref()
+        .and_then(|handle| arenas.get(handle));
+    *geometry = match arena {
+        Some(arena) => arena.geometry(&selection.base_geometry),
+        None => selection.base_geometry.clone(),
+    };
+    info!(
+        "Selected arena {}",
+        arena.map_or("Standard table", |arena| arena.name.as_str())
+    );
+
+    for entity in &table_entities {
+        commands.entity(entity).despawn_recursive();
+    }
+    let surface = arena
+        .and_then(|arena| arena.table_texture.as_ref())
+        .map_or_else(|| textures.table.clone(), |path| asset_server.load(path));
+    spawn_table(&mut commands, surface, &textures, &geometry, &physics);
+    if let Some(arena) = arena {
+        spawn_arena_obstacles(&mut commands, arena, &textures, &physics);
+    }
+
+    for mut text in &mut labels {
+        text.sections[0].value = arena_label(&selection, &arenas);
+    }
+}
+
+fn spawn_arena_obstacles(
+    commands: &mut Commands,
+    arena: &Arena,
+    textures: &Textures,
+    physics: &PuckPhysics,
+) {
+    for bumper in &arena.bumpers {
+        commands
+            .spawn()
+            .insert(TableEntity)
+            .insert(bumper.clone())
+            .insert(RigidBody::Fixed)
+            .insert(Collider::ball(bumper.diameter / 2.0))
+            .insert(surface_restitution(bumper.restitution))
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: Color::ORANGE_RED,
+                    custom_size: Some(Vec2::splat(bumper.diameter)),
+                    ..default()
+                },
+                texture: textures.goal_post.clone(),
+                transform: Transform::from_translation(Vec2::from(bumper.position).extend(1.0)),
+                ..default()
+            });
+    }
+
+    for obstacle in &arena.rotating_obstacles {
+        commands
+            .spawn()
+            .insert(TableEntity)
+            .insert(obstacle.clone())
+            .insert(Wall)
+            .insert(RigidBody::KinematicVelocityBased)
+            .insert(Velocity::angular(obstacle.angular_velocity))
+            .insert(Collider::cuboid(
+                obstacle.length / 2.0,
+                obstacle.thickness / 2.0,
+            ))
+            .insert(surface_restitution(physics.wall_restitution))
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: Color::DARK_GRAY,
+                    custom_size: Some(Vec2::new(obstacle.length, obstacle.thickness)),
+                    ..default()
+                },
+                transform: Transform::from_translation(Vec2::from(obstacle.position).extend(1.0)),
+                ..default()
+            });
+    }
+}
@@ -20,3 +20,26 @@ pub struct Fonts {
     #[asset(path = "fonts/arial.ttf")]
     pub arial: Handle<Font>,
 }
+
+#[derive(AssetCollection)]
+pub struct Sounds {
+    #[asset(path = "sounds/stick_hit.wav")]
+    pub stick_hit: Handle<AudioSource>,
+    #[asset(path = "sounds/wall_hit.wav")]
+    pub wall_hit: Handle<AudioSource>,
+    #[asset(path = "sounds/post_hit.wav")]
+    pub post_hit: Handle<AudioSource>,
+    #[asset(path = "sounds/goal_horn.wav")]
+    pub goal_horn: Handle<AudioSource>,
+    #[asset(path = "sounds/countdown.wav")]
+    pub countdown: Handle<AudioSource>,
+    #[asset(path = "sounds/match_end.wav")]
+    pub match_end: Handle<AudioSource>,
+}
+
+#[derive(AssetCollection)]
+pub struct Arenas {
+    /// Every file in `assets/arenas`, only `.arena.ron` files load as [`crate::arena::Arena`]
+    #[asset(path = "arenas", collection)]
+    pub folder: Vec<HandleUntyped>,
+}
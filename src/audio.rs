@@ -0,0 +1,113 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    assets::Sounds,
+    collision::{PuckHitPost, PuckHitStick, PuckHitWall},
+    score::GoalScored,
+};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    /// Volume multiplier applied to every sound, between 0.0 and 1.0
+    pub master_volume: f32,
+    pub muted: bool,
+    /// Impulse of a puck hit that is played at full volume, weaker hits are quieter
+    pub full_volume_impulse: f32,
+    /// Hits weaker than this impulse are not played at all
+    pub min_impulse: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master_volume: 0.8,
+            muted: false,
+            full_volume_impulse: 50.0,
+            min_impulse: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SoundEffect {
+    Countdown,
+    MatchEnd,
+}
+
+/// Request to play a sound effect that is not tied to a physics or score event
+pub struct PlaySound(pub SoundEffect);
+
+pub fn toggle_mute(keyboard_input: Res<Input<KeyCode>>, mut settings: ResMut<AudioSettings>) {
+    if keyboard_input.just_pressed(KeyCode::M) {
+        settings.muted = !settings.muted;
+        info!("Audio {}", if settings.muted { "muted" } else { "unmuted" });
+    }
+}
+
+fn play(audio: &Audio, settings: &AudioSettings, sound: &Handle<AudioSource>, volume: f32) {
+    if settings.muted {
+        return;
+    }
+    audio.play_with_settings(
+        sound.clone(),
+        PlaybackSettings::ONCE.with_volume(volume * settings.master_volume),
+    );
+}
+
+fn impulse_volume(settings: &AudioSettings, impulse: f32) -> Option<f32> {
+    (impulse >= settings.min_impulse)
+        .then(|| (impulse / settings.full_volume_impulse).clamp(0.1, 1.0))
+}
+
+pub fn play_collision_sounds(
+    audio: Res<Audio>,
+    sounds: Res<Sounds>,
+    settings: Res<AudioSettings>,
+    mut stick_hits: EventReader<PuckHitStick>,
+    mut wall_hits: EventReader<PuckHitWall>,
+    mut post_hits: EventReader<PuckHitPost>,
+) {
+    for hit in stick_hits.iter() {
+        if let Some(volume) = impulse_volume(&settings, hit.impulse) {
+            play(&audio, &settings, &sounds.stick_hit, volume);
+        }
+    }
+    for hit in wall_hits.iter() {
+        if let Some(volume) = impulse_volume(&settings, hit.impulse) {
+            play(&audio, &settings, &sounds.wall_hit, volume);
+        }
+    }
+    for hit in post_hits.iter() {
+        if let Some(volume) = impulse_volume(&settings, hit.impulse) {
+            play(&audio, &settings, &sounds.post_hit, volume);
+        }
+    }
+}
+
+pub fn play_goal_sounds(
+    audio: Res<Audio>,
+    sounds: Res<Sounds>,
+    settings: Res<AudioSettings>,
+    mut goal_events: EventReader<GoalScored>,
+) {
+    for _ in goal_events.iter() {
+        play(&audio, &settings, &sounds.goal_horn, 1.0);
+    }
+}
+
+pub fn play_requested_sounds(
+    audio: Res<Audio>,
+    sounds: Res<Sounds>,
+    settings: Res<AudioSettings>,
+    mut requests: EventReader<PlaySound>,
+) {
+    for PlaySound(effect) in requests.iter() {
+        let sound = match effect {
+            SoundEffect::Countdown => &sounds.countdown,
+            SoundEffect::MatchEnd => &sounds.match_end,
+        };
+        play(&audio, &settings, sound, 1.0);
+    }
+}
@@ -0,0 +1,186 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+/// Thresholds of the rule-based cluster classifier, all lengths in pixels
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ClassificationSettings {
+    pub min_stick_points: usize,
+    pub min_stick_width: f32,
+    pub max_stick_width: f32,
+    pub min_stick_radius: f32,
+    pub max_stick_radius: f32,
+    pub max_stick_linearity: f32,
+    pub min_arm_points: usize,
+    pub min_arm_width: f32,
+    pub min_arm_linearity: f32,
+}
+
+impl Default for ClassificationSettings {
+    fn default() -> Self {
+        Self {
+            min_stick_points: 3,
+            min_stick_width: 30.0,
+            max_stick_width: 160.0,
+            min_stick_radius: 30.0,
+            max_stick_radius: 110.0,
+            max_stick_linearity: 0.98,
+            min_arm_points: 8,
+            min_arm_width: 180.0,
+            min_arm_linearity: 0.9,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClusterClass {
+    Stick,
+    HandOrArm,
+    Unknown,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClusterFeatures {
+    pub point_count: usize,
+    /// Largest distance between two points of the cluster
+    pub width: f32,
+    /// Radius of the least-squares circle through the points, `None` if degenerate
+    pub radius: Option<f32>,
+    /// 0.0 for an isotropic blob, 1.0 for points on a straight line
+    pub linearity: f32,
+    /// Covariance of the point positions around their mean
+    pub covariance: Mat2,
+}
+
+impl ClusterFeatures {
+    pub fn from_points(points: &[Vec2]) -> Self {
+        let point_count = points.len();
+        if point_count == 0 {
+            return Self::default();
+        }
+        let mean = points.iter().sum::<Vec2>() / point_count as f32;
+
+        let width = points
+            .iter()
+            .flat_map(|&left| points.iter().map(move |&right| (left - right).length()))
+            .fold(0.0, f32::max);
+
+        let (xx, xy, yy) = points.iter().fold((0.0, 0.0, 0.0), |(xx, xy, yy), &point| {
+            let offset = point - mean;
+            (
+                xx + offset.x * offset.x,
+                xy + offset.x * offset.y,
+                yy + offset.y * offset.y,
+            )
+        });
+        let trace = xx + yy;
+        let discriminant = ((xx - yy) * (xx - yy) + 4.0 * xy * xy).sqrt();
+        let major = (trace + discriminant) / 2.0;
+        let minor = (trace - discriminant) / 2.0;
+        let linearity = if major > f32::EPSILON {
+            1.0 - minor / major
+        } else {
+            0.0
+        };
+
+        let count = point_count as f32;
+        let covariance = Mat2::from_cols_array(&[xx / count, xy / count, xy / count, yy / count]);
+        Self {
+            point_count,
+            width,
+            radius: fit_circle_radius(points, mean),
+            linearity,
+            covariance,
+        }
+    }
+}
+
+/// Algebraic (Kåsa) circle fit, solved in coordinates relative to `mean` for numerical stability
+fn fit_circle_radius(points: &[Vec2], mean: Vec2) -> Option<f32> {
+    if points.len() < 3 {
+        return None;
+    }
+    // x² + y² + d·x + e·y + f = 0, normal equations of the linear least-squares problem
+    let mut normal = Mat3::ZERO;
+    let mut rhs = Vec3::ZERO;
+    for &point in points {
+        let offset = point - mean;
+        let row = Vec3::new(offset.x, offset.y, 1.0);
+        let target = -offset.length_squared();
+        normal = normal + Mat3::from_cols(row * row.x, row * row.y, row * row.z);
+        rhs += row * target;
+    }
+    if normal.determinant().abs() < f32::EPSILON {
+        return None;
+    }
+    let solution = normal.inverse() * rhs;
+    let radius_squared = (solution.x * solution.x + solution.y * solution.y) / 4.0 - solution.z;
+    (radius_squared > 0.0).then(|| radius_squared.sqrt())
+}
+
+pub fn classify(features: &ClusterFeatures, settings: &ClassificationSettings) -> ClusterClass {
+    let is_stick_sized = features.point_count >= settings.min_stick_points
+        && (settings.min_stick_width..=settings.max_stick_width).contains(&features.width)
+        && features.linearity <= settings.max_stick_linearity
+        && features.radius.map_or(false, |radius| {
+            (settings.min_stick_radius..=settings.max_stick_radius).contains(&radius)
+        });
+    if is_stick_sized {
+        return ClusterClass::Stick;
+    }
+
+    let is_arm_sized = features.point_count >= settings.min_arm_points
+        && (features.width >= settings.min_arm_width
+            || features.linearity >= settings.min_arm_linearity);
+    if is_arm_sized {
+        return ClusterClass::HandOrArm;
+    }
+
+    ClusterClass::Unknown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points a lidar sees on the near side of a round stick
+    fn arc(center: Vec2, radius: f32, count: usize) -> Vec<Vec2> {
+        (0..count)
+            .map(|index| {
+                let angle = (-60.0 + 120.0 * index as f32 / (count - 1) as f32).to_radians();
+                center + Vec2::from_angle(angle) * radius
+            })
+            .collect()
+    }
+
+    #[test]
+    fn circle_fit_finds_radius_of_arc() {
+        let features = ClusterFeatures::from_points(&arc(Vec2::new(100.0, 200.0), 60.0, 8));
+        assert_eq!(features.point_count, 8);
+        let radius = features.radius.unwrap();
+        assert!((radius - 60.0).abs() < 0.1, "radius {}", radius);
+    }
+
+    #[test]
+    fn circle_fit_needs_three_points() {
+        let features = ClusterFeatures::from_points(&[Vec2::ZERO, Vec2::X * 50.0]);
+        assert_eq!(features.radius, None);
+        assert_eq!(features.width, 50.0);
+    }
+
+    #[test]
+    fn classifies_by_size_and_shape() {
+        let settings = ClassificationSettings::default();
+        let stick = ClusterFeatures::from_points(&arc(Vec2::ZERO, 60.0, 8));
+        assert_eq!(classify(&stick, &settings), ClusterClass::Stick);
+
+        let line: Vec<Vec2> = (0..12)
+            .map(|index| Vec2::new(index as f32 * 30.0, 5.0 * (index % 2) as f32))
+            .collect();
+        let arm = ClusterFeatures::from_points(&line);
+        assert_eq!(classify(&arm, &settings), ClusterClass::HandOrArm);
+
+        let noise = ClusterFeatures::from_points(&[Vec2::ZERO, Vec2::ONE]);
+        assert_eq!(classify(&noise, &settings), ClusterClass::Unknown);
+    }
+}
@@ -0,0 +1,111 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    player::{Player, Team},
+    puck::Puck,
+    stick::Stick,
+    table::{GoalPost, Wall},
+};
+
+/// Impulses are the summed contact impulses of the first physics step of a contact, in rapier units
+pub struct PuckHitStick {
+    pub puck: Entity,
+    // Not read by any system yet, kept for consumers of the event
+    #[allow(dead_code)]
+    pub stick: Entity,
+    pub player: usize,
+    pub team: Team,
+    pub impulse: f32,
+}
+
+pub struct PuckHitWall {
+    // Not read by any system yet, kept for consumers of the event
+    #[allow(dead_code)]
+    pub puck: Entity,
+    pub impulse: f32,
+}
+
+pub struct PuckHitPost {
+    // Not read by any system yet, kept for consumers of the event
+    #[allow(dead_code)]
+    pub puck: Entity,
+    /// Team defending the goal the post belongs to
+    #[allow(dead_code)]
+    pub team: Team,
+    pub impulse: f32,
+}
+
+/// Team and id of the player whose stick last hit a puck
+#[derive(Component, Default)]
+pub struct LastTouch(pub Option<(Team, usize)>);
+
+fn contact_impulse(rapier_context: &RapierContext, first: Entity, second: Entity) -> f32 {
+    rapier_context
+        .contact_pair(first, second)
+        .map(|contact_pair| {
+            contact_pair
+                .manifolds()
+                .flat_map(|manifold| manifold.points().map(|point| point.impulse()))
+                .sum()
+        })
+        .unwrap_or_default()
+}
+
+pub fn emit_puck_collision_events(
+    mut collision_events: EventReader<CollisionEvent>,
+    rapier_context: Res<RapierContext>,
+    // Pucks waiting for their serve are sensors as well
+    pucks: Query<(), (With<Puck>, Without<Sensor>)>,
+    // Lost sticks are sensors and must not report hits
+    sticks: Query<(&Player, &Team), (With<Stick>, Without<Sensor>)>,
+    walls: Query<(), With<Wall>>,
+    posts: Query<&GoalPost>,
+    mut stick_hits: EventWriter<PuckHitStick>,
+    mut wall_hits: EventWriter<PuckHitWall>,
+    mut post_hits: EventWriter<PuckHitPost>,
+) {
+    for collision_event in collision_events.iter() {
+        let (first, second) = match *collision_event {
+            CollisionEvent::Started(first, second, _) => (first, second),
+            CollisionEvent::Stopped(..) => continue,
+        };
+        let (puck, other) = if pucks.contains(first) {
+            (first, second)
+        } else if pucks.contains(second) {
+            (second, first)
+        } else {
+            continue;
+        };
+        let impulse = contact_impulse(&rapier_context, puck, other);
+
+        if let Ok((player, &team)) = sticks.get(other) {
+            stick_hits.send(PuckHitStick {
+                puck,
+                stick: other,
+                player: player.id,
+                team,
+                impulse,
+            });
+        } else if walls.contains(other) {
+            wall_hits.send(PuckHitWall { puck, impulse });
+        } else if let Ok(post) = posts.get(other) {
+            post_hits.send(PuckHitPost {
+                puck,
+                team: post.team,
+                impulse,
+            });
+        }
+    }
+}
+
+pub fn track_last_touch(
+    mut stick_hits: EventReader<PuckHitStick>,
+    mut pucks: Query<&mut LastTouch>,
+) {
+    for hit in stick_hits.iter() {
+        if let Ok(mut last_touch) = pucks.get_mut(hit.puck) {
+            last_touch.0 = Some((hit.team, hit.player));
+        }
+    }
+}
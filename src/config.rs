@@ -0,0 +1,81 @@
+use serde::Deserialize;
+
+use crate::{
+    audio::AudioSettings, classification::ClassificationSettings, export::ExportSettings,
+    physics::PuckPhysics, player::Roster, power_up::PowerUpSettings,
+    prediction::PredictionSettings, puck::MultiPuckSettings, replay::ReplaySettings,
+    rules::MatchSettings, serve::ServeSettings, simulation::SimulationSettings,
+    stick::StickSettings, stuck::StuckPuckSettings, table::TableGeometry,
+    tracking::TrackingSettings, ui::ScoreDisplaySettings, zone::ZoneSettings,
+};
+
+const DEFAULT_CONFIG_PATH: &str = "config.ron";
+
+/// Runtime configuration read from `config.ron` or the file passed with `--config`, missing
+/// fields fall back to their defaults
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub table: TableGeometry,
+    pub classification: ClassificationSettings,
+    pub tracking: TrackingSettings,
+    pub stick: StickSettings,
+    pub zones: ZoneSettings,
+    pub players: Roster,
+    pub export: ExportSettings,
+    pub audio: AudioSettings,
+    pub puck: PuckPhysics,
+    pub serve: ServeSettings,
+    pub rules: MatchSettings,
+    pub prediction: PredictionSettings,
+    pub multi_puck: MultiPuckSettings,
+    pub power_ups: PowerUpSettings,
+    pub replay: ReplaySettings,
+    pub simulation: SimulationSettings,
+    pub stuck_pucks: StuckPuckSettings,
+    pub score_displays: ScoreDisplaySettings,
+}
+
+/// Command line arguments
+pub struct Arguments {
+    /// `--config <path>`
+    pub config_path: String,
+    /// `--tracker`, start in the tracking diagnostics view instead of a game
+    pub tracker: bool,
+    /// `--record-scans <path>`, write every handled lidar message with its simulation step
+    pub record_scans: Option<String>,
+    /// `--play-scans <path>`, replace the lidar with a recording made with `--record-scans`
+    pub play_scans: Option<String>,
+}
+
+impl Arguments {
+    pub fn parse() -> Self {
+        let arguments: Vec<_> = std::env::args().skip(1).collect();
+        let value = |name: &str| {
+            arguments
+                .iter()
+                .skip_while(|&argument| argument != name)
+                .nth(1)
+                .cloned()
+        };
+        Self {
+            config_path: value("--config").unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string()),
+            tracker: arguments.iter().any(|argument| argument == "--tracker"),
+            record_scans: value("--record-scans"),
+            play_scans: value("--play-scans"),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &str) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(content) => ron::from_str(&content)
+                .unwrap_or_else(|error| panic!("Failed to parse {path}: {error}")),
+            Err(error) => {
+                eprintln!("Failed to read {path}, using defaults: {error}");
+                Self::default()
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    lidar_communication::LidarScan, player::Player, puck::Puck, simulation::SimulationClock,
+    stick::Stick, tracking::Tracks, trajectory::Trajectories,
+};
+
+const CSV_HEADER: &str = "time,kind,id,x,y";
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ExportSettings {
+    /// Directory the CSV files are written to, created if missing
+    pub directory: PathBuf,
+    /// Record the tracks, sticks and pucks of every match to a file of its own
+    pub continuous: bool,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("recordings"),
+            continuous: false,
+        }
+    }
+}
+
+/// Continuous recording of every scan's tracks and every simulation step's stick and puck
+/// positions, all timestamped with the simulation clock
+#[derive(Default)]
+pub struct TrajectoryRecorder {
+    writer: Option<BufWriter<File>>,
+}
+
+impl TrajectoryRecorder {
+    pub fn start(&mut self, settings: &ExportSettings) {
+        self.stop();
+        match create_csv(settings, "match") {
+            Ok((path, writer)) => {
+                info!("Recording trajectories to {}", path.display());
+                self.writer = Some(writer);
+            }
+            Err(error) => error!("Failed to start recording: {error}"),
+        }
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(mut writer) = self.writer.take() {
+            if let Err(error) = writer.flush() {
+                error!("Failed to finish recording: {error}");
+            }
+            info!("Stopped recording trajectories");
+        }
+    }
+
+    /// Starts a new file for a match that is starting if matches are recorded continuously
+    pub fn start_match(&mut self, settings: &ExportSettings) {
+        if settings.continuous {
+            self.start(settings);
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    fn write_row(&mut self, time: f64, kind: &str, id: impl std::fmt::Display, position: Vec2) {
+        if let Some(writer) = &mut self.writer {
+            if let Err(error) = writeln!(
+                writer,
+                "{time:.4},{kind},{id},{},{}",
+                position.x, position.y
+            ) {
+                error!("Failed to write recording, stopping it: {error}");
+                self.writer = None;
+            }
+        }
+    }
+}
+
+fn create_csv(settings: &ExportSettings, prefix: &str) -> io::Result<(PathBuf, BufWriter<File>)> {
+    fs::create_dir_all(&settings.directory)?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = settings.directory.join(format!("{prefix}-{timestamp}.csv"));
+    let mut writer = BufWriter::new(File::create(&path)?);
+    writeln!(writer, "{CSV_HEADER}")?;
+    Ok((path, writer))
+}
+
+/// `E` writes the buffered track trajectories to a file, `C` starts or stops a continuous recording
+pub fn detect_export_key_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    settings: Res<ExportSettings>,
+    trajectories: Res<Trajectories>,
+    mut recorder: ResMut<TrajectoryRecorder>,
+) {
+    if keyboard_input.just_pressed(KeyCode::E) {
+        let result = create_csv(&settings, "trajectories").and_then(|(path, mut writer)| {
+            for (id, buffer) in &trajectories.buffers {
+                for (time, position) in &buffer.data {
+                    writeln!(writer, "{time:.4},track,{id},{},{}", position.x, position.y)?;
+                }
+            }
+            writer.flush()?;
+            Ok(path)
+        });
+        match result {
+            Ok(path) => info!("Exported trajectories to {}", path.display()),
+            Err(error) => error!("Failed to export trajectories: {error}"),
+        }
+    } else if keyboard_input.just_pressed(KeyCode::C) {
+        if recorder.is_recording() {
+            recorder.stop();
+        } else {
+            recorder.start(&settings);
+        }
+    }
+}
+
+/// Starts the file of a match started from the lobby
+pub fn start_match_recording(
+    settings: Res<ExportSettings>,
+    mut recorder: ResMut<TrajectoryRecorder>,
+) {
+    recorder.start_match(&settings);
+}
+
+/// Ends the file of a match once it is over or left for the lobby
+pub fn stop_match_recording(
+    settings: Res<ExportSettings>,
+    mut recorder: ResMut<TrajectoryRecorder>,
+) {
+    if settings.continuous {
+        recorder.stop();
+    }
+}
+
+pub fn record_trajectories_continuously(
+    clock: Res<SimulationClock>,
+    mut recorder: ResMut<TrajectoryRecorder>,
+    mut scans: EventReader<LidarScan>,
+    tracks: Res<Tracks>,
+    sticks: Query<(&Player, &Transform), With<Stick>>,
+    pucks: Query<(Entity, &Transform), With<Puck>>,
+) {
+    if !recorder.is_recording() {
+        return;
+    }
+
+    for scan in scans.iter() {
+        for track in &tracks.tracks {
+            recorder.write_row(scan.time, "track", track.id, track.position);
+        }
+    }
+    let now = clock.seconds_since_startup();
+    for (player, transform) in &sticks {
+        recorder.write_row(now, "stick", player.id, transform.translation.truncate());
+    }
+    for (entity, transform) in &pucks {
+        recorder.write_row(now, "puck", entity.id(), transform.translation.truncate());
+    }
+}
@@ -1,35 +1,52 @@
 use bevy::prelude::*;
-use bevy_rapier2d::prelude::Velocity;
 use iyes_loopless::state::{CurrentState, NextState};
 
 use crate::{
     app_state::{AppState, GameState},
-    puck::Puck,
-    score::Score,
+    export::{ExportSettings, TrajectoryRecorder},
+    puck::{Puck, RemovedPuck},
+    rules::{restart_match, MatchSettings},
+    serve::ServeSettings,
 };
 
 pub fn detect_game_key_input(
     mut commands: Commands,
     keyboard_input: Res<Input<KeyCode>>,
-    app_state: Res<CurrentState<AppState>>,
-    mut score: ResMut<Score>,
-    mut pucks: Query<(&mut Transform, &mut Velocity), With<Puck>>,
+    match_settings: Res<MatchSettings>,
+    serve_settings: Res<ServeSettings>,
+    export_settings: Res<ExportSettings>,
+    mut recorder: ResMut<TrajectoryRecorder>,
+    pucks: Query<Entity, (With<Puck>, Without<RemovedPuck>)>,
 ) {
     if keyboard_input.just_pressed(KeyCode::R) {
-        for (mut transform, mut velocity) in &mut pucks {
-            transform.translation.x = 0.0;
-            transform.translation.y = 0.0;
-            *velocity = Velocity::zero();
+        restart_match(&mut commands, &match_settings, &serve_settings, &pucks);
+        recorder.start_match(&export_settings);
+    }
+}
+
+/// `Space` pauses and resumes a game, `Escape` pauses it or leaves a paused game or the match over
+/// screen for the lobby. Both keys are ignored in every other state.
+pub fn detect_state_key_input(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    app_state: Res<CurrentState<AppState>>,
+) {
+    let next_state = if keyboard_input.just_pressed(KeyCode::Space) {
+        match app_state.0 {
+            AppState::Game(GameState::Running) => Some(AppState::Game(GameState::Paused)),
+            AppState::Game(GameState::Paused) => Some(AppState::Game(GameState::Running)),
+            _ => None,
+        }
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+        match app_state.0 {
+            AppState::Game(GameState::Running) => Some(AppState::Game(GameState::Paused)),
+            AppState::Game(GameState::Paused) | AppState::MatchOver => Some(AppState::Lobby),
+            _ => None,
         }
-        *score = Score::default();
-    } else if keyboard_input.just_pressed(KeyCode::Space) {
-        let toggled_state = match app_state.0 {
-            AppState::Game(game_state) => match game_state {
-                GameState::Running => GameState::Paused,
-                GameState::Paused => GameState::Running,
-            },
-            _ => panic!("Cannot toggle pause state if not in game"),
-        };
-        commands.insert_resource(NextState(toggled_state));
+    } else {
+        None
+    };
+    if let Some(next_state) = next_state {
+        commands.insert_resource(NextState(next_state));
     }
 }
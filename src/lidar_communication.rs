@@ -2,12 +2,19 @@ use bevy::prelude::*;
 use bevy_prototype_debug_lines::DebugLines;
 use iyes_loopless::state::NextState;
 use scrapinator::Lidar;
-use std::thread::spawn;
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    thread::spawn,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    app_state::{AppState, GameState},
-    stick::{LeftStick, RightStick},
-    TABLE_LENGTH, TABLE_WIDTH,
+    app_state::AppState,
+    classification::{classify, ClassificationSettings, ClusterClass, ClusterFeatures},
+    config::Arguments,
+    simulation::SimulationClock,
+    table::TableGeometry,
 };
 
 #[derive(Component)]
@@ -15,45 +22,161 @@ pub struct LidarSettings {
     pub pixels_per_meter: f32,
 }
 
+pub struct LidarMessage {
+    pub received: Instant,
+    pub rays: Vec<usize>,
+    /// Simulation step a recorded message was handled in, `None` for live messages and the
+    /// calibration
+    pub step: Option<u64>,
+}
+
+/// Writes every handled lidar message, one per line as the step (`-` for the calibration)
+/// followed by the rays
+pub struct ScanRecorder {
+    writer: BufWriter<File>,
+}
+
+impl ScanRecorder {
+    fn record(&mut self, step: Option<u64>, rays: &[usize]) {
+        let step = step.map_or_else(|| "-".to_string(), |step| step.to_string());
+        let rays: Vec<_> = rays.iter().map(usize::to_string).collect();
+        // Flushed right away, the app may exit without dropping its resources
+        let result =
+            writeln!(self.writer, "{} {}", step, rays.join(" ")).and_then(|_| self.writer.flush());
+        if let Err(error) = result {
+            error!("Failed to record scan: {error}");
+        }
+    }
+}
+
+fn read_scan_recording(path: &str) -> Vec<LidarMessage> {
+    let content = fs::read_to_string(path)
+        .unwrap_or_else(|error| panic!("Failed to read scans from {path}: {error}"));
+    content
+        .lines()
+        .map(|line| {
+            let mut values = line.split_whitespace();
+            let step = values.next().and_then(|step| step.parse().ok());
+            let rays = values
+                .map(|ray| {
+                    ray.parse()
+                        .unwrap_or_else(|error| panic!("Invalid ray in {path}: {error}"))
+                })
+                .collect();
+            LidarMessage {
+                received: Instant::now(),
+                rays,
+                step,
+            }
+        })
+        .collect()
+}
+
 pub struct LidarChannel {
-    pub receiver: flume::Receiver<Vec<usize>>,
+    pub receiver: flume::Receiver<LidarMessage>,
+}
+
+/// Clustered points of one lidar message, sent whenever a new message is handled
+#[derive(Clone)]
+pub struct LidarScan {
+    pub time: f64,
+    /// Time from receiving the message in the communication thread until it was processed
+    pub latency: Duration,
+    pub clusters: Vec<Cluster>,
 }
 
-pub fn setup_lidar_communication(mut commands: Commands) {
+#[derive(SystemLabel, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LidarSystem {
+    ReceiveScan,
+    UpdateTracks,
+    FollowTracks,
+    DriveComputers,
+}
+
+pub fn setup_lidar_communication(mut commands: Commands, arguments: Res<Arguments>) {
     let (sender, receiver) = flume::unbounded();
-    spawn(move || {
-        info!("Starting communication thread...");
-        let mut lidar = Lidar::connect("192.168.0.1:2112");
-        loop {
-            let data = lidar.poll_data();
-            sender.send(data).unwrap();
+    if let Some(path) = &arguments.record_scans {
+        match File::create(path) {
+            Ok(file) => {
+                info!("Recording scans to {}", path);
+                commands.insert_resource(ScanRecorder {
+                    writer: BufWriter::new(file),
+                });
+            }
+            Err(error) => error!("Failed to record scans to {path}: {error}"),
         }
-    });
+    }
+    if let Some(path) = &arguments.play_scans {
+        info!("Playing scans from {}", path);
+        for message in read_scan_recording(path) {
+            sender.send(message).unwrap();
+        }
+    } else {
+        spawn(move || {
+            info!("Starting communication thread...");
+            let mut lidar = Lidar::connect("192.168.0.1:2112");
+            loop {
+                let rays = lidar.poll_data();
+                sender
+                    .send(LidarMessage {
+                        received: Instant::now(),
+                        rays,
+                        step: None,
+                    })
+                    .unwrap();
+            }
+        });
+    }
     commands.insert_resource(LidarChannel { receiver });
     commands.insert_resource(LidarSettings {
         pixels_per_meter: 1300.0,
     });
 }
 
-pub fn wait_for_lidar_messages(mut commands: Commands, lidar_channel: Res<LidarChannel>) {
-    if lidar_channel
-        .receiver
-        .try_iter()
-        .any(|message| !message.is_empty())
-    {
+pub fn wait_for_lidar_messages(
+    mut commands: Commands,
+    arguments: Res<Arguments>,
+    lidar_channel: Res<LidarChannel>,
+) {
+    // A recording starts with the calibration message, which must not be skipped
+    let is_receiving = if arguments.play_scans.is_some() {
+        !lidar_channel.receiver.is_empty()
+    } else {
+        lidar_channel
+            .receiver
+            .try_iter()
+            .any(|message| !message.rays.is_empty())
+    };
+    if is_receiving {
         commands.insert_resource(NextState(AppState::Calibration))
     }
 }
 
+/// Discards live messages while nothing is tracked, so that the channel does not grow and tracking
+/// resumes with the current scan instead of a backlog
+pub fn drain_lidar_messages(arguments: Res<Arguments>, lidar_channel: Res<LidarChannel>) {
+    // Recorded messages belong to the steps they were handled in and are kept for them
+    if arguments.play_scans.is_some() {
+        return;
+    }
+    for _ in lidar_channel.receiver.try_iter() {}
+}
+
 pub fn lidar_calibration(
     mut commands: Commands,
+    arguments: Res<Arguments>,
+    geometry: Res<TableGeometry>,
     lidar_channel: Res<LidarChannel>,
     mut lidar_settings: ResMut<LidarSettings>,
+    recorder: Option<ResMut<ScanRecorder>>,
 ) {
-    if let Ok(rays) = lidar_channel.receiver.try_recv() {
+    if let Ok(message) = lidar_channel.receiver.try_recv() {
         info!("Calibrating...");
-        let points = process_lidar_message(rays, 1.0);
-        let origin = Vec2::new(0.0, -TABLE_WIDTH / 2.0);
+        if let Some(mut recorder) = recorder {
+            recorder.record(None, &message.rays);
+        }
+        let points = process_lidar_message(message.rays, 1.0, &geometry);
+        let origin = geometry.lidar_origin();
         let closest = points
             .iter()
             .min_by(|&left, &right| {
@@ -65,14 +188,22 @@ pub fn lidar_calibration(
         info!("Closest: {}", closest);
         let distance = origin - *closest;
         dbg!(distance);
-        lidar_settings.pixels_per_meter = (TABLE_WIDTH / 2.0) / distance.length();
+        lidar_settings.pixels_per_meter = (geometry.width / 2.0) / distance.length();
         dbg!(lidar_settings.pixels_per_meter);
-        commands.insert_resource(NextState(AppState::Game(GameState::Running)));
-        //commands.insert_resource(NextState(AppState::Tracker));
+        if arguments.tracker {
+            commands.insert_resource(NextState(AppState::Tracker));
+        } else {
+            commands.insert_resource(NextState(AppState::Lobby));
+        }
     }
 }
 
-pub fn process_lidar_message(rays: Vec<usize>, pixels_per_meter: f32) -> Vec<Vec2> {
+pub fn process_lidar_message(
+    rays: Vec<usize>,
+    pixels_per_meter: f32,
+    geometry: &TableGeometry,
+) -> Vec<Vec2> {
+    let origin = geometry.lidar_origin();
     //let layer_index = 0;
     rays.into_iter()
         .filter(|&distance| distance != 0)
@@ -82,16 +213,9 @@ pub fn process_lidar_message(rays: Vec<usize>, pixels_per_meter: f32) -> Vec<Vec
             let theta = -138.0 + 0.75 * i as f32;
             let x = distance * theta.to_radians().cos();
             let y = distance * theta.to_radians().sin();
-            Vec2::new(
-                -y * pixels_per_meter,
-                x * pixels_per_meter - TABLE_WIDTH / 2.0,
-            )
-        })
-        .filter(|point| {
-            let table_x = -TABLE_LENGTH / 2.0..TABLE_LENGTH / 2.0;
-            let table_y = -TABLE_WIDTH / 2.0..TABLE_WIDTH / 2.0;
-            table_x.contains(&point.x) && table_y.contains(&point.y)
+            origin + Vec2::new(-y * pixels_per_meter, x * pixels_per_meter)
         })
+        .filter(|&point| geometry.contains(point))
         .collect()
 }
 
@@ -106,9 +230,12 @@ pub fn scale_lidar(mut lidar_settings: ResMut<LidarSettings>, keyboard_input: Re
     lidar_settings.pixels_per_meter += 2.0 * scale;
 }
 
+#[derive(Clone)]
 pub struct Cluster {
     pub center: Vec2,
     pub points: Vec<Vec2>,
+    pub features: ClusterFeatures,
+    pub class: ClusterClass,
 }
 
 impl Cluster {
@@ -116,6 +243,8 @@ impl Cluster {
         Self {
             center,
             points: vec![center],
+            features: ClusterFeatures::default(),
+            class: ClusterClass::Unknown,
         }
     }
 
@@ -124,21 +253,71 @@ impl Cluster {
         let length = self.points.len();
         self.center = self.points.iter().sum::<Vec2>() / length as f32;
     }
+
+    pub fn annotate(&mut self, settings: &ClassificationSettings) {
+        self.features = ClusterFeatures::from_points(&self.points);
+        self.class = classify(&self.features, settings);
+    }
+}
+
+pub fn cluster_points(points: &[Vec2], settings: &ClassificationSettings) -> Vec<Cluster> {
+    let mut clusters = Vec::<Cluster>::new();
+    for &point in points {
+        let is_close_to_cluster = clusters
+            .iter_mut()
+            .find(|cluster| (cluster.center - point).length() < 100.0);
+        if let Some(cluster) = is_close_to_cluster {
+            cluster.add(point);
+            continue;
+        }
+        clusters.push(Cluster::new(point));
+    }
+    for cluster in &mut clusters {
+        cluster.annotate(settings);
+    }
+    clusters
+}
+
+/// Latest live message, or the last recorded message handled up to `step`, recorded messages of
+/// later steps wait in `pending`
+fn next_message(
+    receiver: &flume::Receiver<LidarMessage>,
+    pending: &mut Option<LidarMessage>,
+    step: u64,
+) -> Option<LidarMessage> {
+    let mut latest = None;
+    for message in pending.take().into_iter().chain(receiver.try_iter()) {
+        match message.step {
+            Some(recorded) if recorded > step => {
+                *pending = Some(message);
+                break;
+            }
+            _ => latest = Some(message),
+        }
+    }
+    latest
 }
 
 pub fn handle_lidar_data(
     lidar_settings: Res<LidarSettings>,
+    classification_settings: Res<ClassificationSettings>,
+    geometry: Res<TableGeometry>,
     lidar_channel: Res<LidarChannel>,
+    clock: Res<SimulationClock>,
     mut lines: ResMut<DebugLines>,
-    mut left_stick: Query<&mut Transform, (With<LeftStick>, Without<RightStick>)>,
-    mut right_stick: Query<&mut Transform, (Without<LeftStick>, With<RightStick>)>,
+    mut scans: EventWriter<LidarScan>,
+    recorder: Option<ResMut<ScanRecorder>>,
+    mut pending: Local<Option<LidarMessage>>,
 ) {
-    if let Some(rays) = lidar_channel.receiver.try_iter().last() {
-        let mut clusters = Vec::<Cluster>::new();
-        if rays.is_empty() {
+    if let Some(message) = next_message(&lidar_channel.receiver, &mut pending, clock.steps()) {
+        if message.rays.is_empty() {
             return;
         }
-        let mut points: Vec<_> = process_lidar_message(rays, lidar_settings.pixels_per_meter);
+        if let Some(mut recorder) = recorder {
+            recorder.record(Some(clock.steps()), &message.rays);
+        }
+        let mut points: Vec<_> =
+            process_lidar_message(message.rays, lidar_settings.pixels_per_meter, &geometry);
         // for points in points.windows(2) {
         //     let left = points[0];
         //     let right = points[1];
@@ -150,44 +329,58 @@ pub fn handle_lidar_data(
         //     );
         // }
         points.sort_unstable_by(|left, right| {
-            let origin = Vec2::new(0.0, -TABLE_WIDTH / 2.0);
+            let origin = geometry.lidar_origin();
             let left_distance = (*left - origin).length();
             let right_distance = (*right - origin).length();
             left_distance.total_cmp(&right_distance)
         });
-        for &point in &points {
-            let is_close_to_cluster = clusters
-                .iter_mut()
-                .find(|cluster| (cluster.center - point).length() < 100.0);
-            if let Some(cluster) = is_close_to_cluster {
-                cluster.add(point);
-                continue;
-            }
-            clusters.push(Cluster::new(point));
+        let clusters = cluster_points(&points, &classification_settings);
+        scans.send(LidarScan {
+            time: clock.seconds_since_startup(),
+            latency: message.received.elapsed(),
+            clusters,
+        });
+    } else {
+        warn!("No messages in the channel");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(step: Option<u64>, ray: usize) -> LidarMessage {
+        LidarMessage {
+            received: Instant::now(),
+            rays: vec![ray],
+            step,
         }
-        let low_pass = 0.95;
-        let left = clusters
-            .iter()
-            .find(|position| position.center.x.is_sign_negative());
-        let mut left_stick = left_stick.single_mut();
-        if let Some(cluster) = left {
-            left_stick.translation.x =
-                (1.0 - low_pass) * left_stick.translation.x + low_pass * cluster.center.x;
-            left_stick.translation.y =
-                (1.0 - low_pass) * left_stick.translation.y + low_pass * cluster.center.y;
+    }
+
+    #[test]
+    fn next_message_waits_for_the_recorded_step() {
+        let (sender, receiver) = flume::unbounded();
+        for (step, ray) in [(0, 1), (0, 2), (2, 3), (3, 4)] {
+            sender.send(message(Some(step), ray)).unwrap();
         }
+        let mut pending = None;
 
-        let right = clusters
-            .iter()
-            .find(|position| position.center.x.is_sign_positive());
-        let mut right_stick = right_stick.single_mut();
-        if let Some(cluster) = right {
-            right_stick.translation.x =
-                (1.0 - low_pass) * right_stick.translation.x + low_pass * cluster.center.x;
-            right_stick.translation.y =
-                (1.0 - low_pass) * right_stick.translation.y + low_pass * cluster.center.y;
+        let latest = next_message(&receiver, &mut pending, 0).unwrap();
+        assert_eq!(latest.rays, [2]);
+        assert!(next_message(&receiver, &mut pending, 1).is_none());
+        assert_eq!(next_message(&receiver, &mut pending, 2).unwrap().rays, [3]);
+        assert_eq!(next_message(&receiver, &mut pending, 5).unwrap().rays, [4]);
+        assert!(pending.is_none());
+    }
+
+    #[test]
+    fn next_message_takes_latest_live_message() {
+        let (sender, receiver) = flume::unbounded();
+        for ray in 1..=3 {
+            sender.send(message(None, ray)).unwrap();
         }
-    } else {
-        warn!("No messages in the channel");
+        let mut pending = None;
+        assert_eq!(next_message(&receiver, &mut pending, 7).unwrap().rays, [3]);
+        assert!(next_message(&receiver, &mut pending, 8).is_none());
     }
 }
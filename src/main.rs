@@ -1,59 +1,139 @@
-use app_state::AppState;
-use assets::{Fonts, Textures};
-use bevy::prelude::*;
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
+use ai::drive_computer_players;
+use app_state::{AppState, GameState};
+use arena::{select_arena, setup_arena_label, Arena, ArenaLoader, ArenaSelection};
+use assets::{Arenas, Fonts, Sounds, Textures};
+use audio::{
+    play_collision_sounds, play_goal_sounds, play_requested_sounds, toggle_mute, PlaySound,
+};
+use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, prelude::*};
 use bevy_asset_loader::prelude::*;
 use bevy_prototype_debug_lines::DebugLinesPlugin;
 use bevy_rapier2d::prelude::*;
 use camera::{setup_camera, zoom_camera};
-use input::detect_game_key_input;
+use collision::{
+    emit_puck_collision_events, track_last_touch, PuckHitPost, PuckHitStick, PuckHitWall,
+};
+use config::{Arguments, Config};
+use export::{
+    detect_export_key_input, record_trajectories_continuously, start_match_recording,
+    stop_match_recording, TrajectoryRecorder,
+};
+use input::{detect_game_key_input, detect_state_key_input};
 use iyes_loopless::prelude::*;
 use lidar_communication::{
-    handle_lidar_data, lidar_calibration, scale_lidar, setup_lidar_communication,
-    wait_for_lidar_messages,
+    drain_lidar_messages, handle_lidar_data, lidar_calibration, scale_lidar,
+    setup_lidar_communication, wait_for_lidar_messages, LidarScan, LidarSystem,
+};
+use physics::{
+    apply_surface_physics, limit_puck_speed, pause_physics, resume_physics, setup_tuning_panel,
+    update_tuning_panel,
+};
+use power_up::{
+    clear_power_ups, collect_power_ups, expire_power_up_effects, spawn_power_ups, PowerUps,
 };
-use puck::setup_puck;
-use score::{detect_goals, Score};
-use stick::setup_stick;
+use prediction::draw_predicted_paths;
+use puck::{despawn_removed_pucks, setup_puck, spawn_additional_pucks};
+use replay::{
+    cleanup_replay, play_replay, record_replay_frames, setup_replay, start_replay, ReplayBuffer,
+};
+use rules::{
+    apply_match_rules, cleanup_lobby, cleanup_match_over_screen, leave_match_over_screen,
+    setup_lobby, setup_match_over_screen, start_match_from_lobby, update_match_clock, Match,
+    MatchSystem,
+};
+use score::{detect_goals, update_score, GoalScored, Score};
+use serve::run_serves;
+use simulation::{
+    advance_simulation_clock, is_tracking, SimulationPlugin, SimulationStage, SIMULATION_STEP,
+};
+use stick::{follow_tracks, setup_stick, update_stick_presence};
+use stuck::{fade_stuck_indicators, recover_escaped_pucks, recover_stuck_pucks};
 use table::setup_table;
-use trajectory::track_object;
-use ui::{setup_ui, update_score_ui};
+use tracker_view::{
+    cleanup_tracker_view, draw_scan, draw_tracks, setup_tracker_view, toggle_tracker_view,
+    update_tracker_stats,
+};
+use tracking::{update_tracks, Tracks};
+use trajectory::{draw_trajectories, record_trajectories, Trajectories};
+use ui::{
+    cleanup_pause_overlay, setup_pause_overlay, setup_ui, update_game_time_ui, update_score_ui,
+    update_serve_countdown_ui,
+};
+use zone::draw_player_zones;
 
+mod ai;
 mod app_state;
+mod arena;
 mod assets;
+mod audio;
 mod camera;
+mod classification;
+mod collision;
+mod config;
+mod export;
 mod input;
 mod lidar_communication;
+mod physics;
+mod player;
+mod power_up;
+mod prediction;
 mod puck;
+mod replay;
+mod rules;
 mod score;
+mod serve;
+mod simulation;
 mod stick;
+mod stuck;
 mod table;
+mod tracker_view;
+mod tracking;
 mod trajectory;
 mod ui;
+mod zone;
 
 const BACKGROUND_COLOR: Color = Color::rgb(0.5, 0.5, 0.5);
 
-const TABLE_WIDTH: f32 = 1200.0;
-const TABLE_LENGTH: f32 = 1920.0;
-const GOAL_WIDTH: f32 = 400.0;
-const GOAL_POST_DIAMETER: f32 = 40.0;
-const SCORE_FONT_SIZE: f32 = 400.0;
-const PUCK_DIAMETER: f32 = 64.0;
-const STICK_DIAMETER: f32 = 120.0;
-//const TIMER_FONT_SIZE: f32 = 55.0;
-//const GAME_DURATION: Duration = Duration::from_secs(60);
+const TIMER_FONT_SIZE: f32 = 55.0;
 
 fn main() {
+    let arguments = Arguments::parse();
+    let config = Config::load(&arguments.config_path);
+
+    let is_in_game =
+        |app_state: Res<CurrentState<AppState>>| matches!(app_state.0, AppState::Game(_));
+    let is_waiting = |app_state: Res<CurrentState<AppState>>| {
+        matches!(
+            app_state.0,
+            AppState::Lobby
+                | AppState::Game(GameState::Paused)
+                | AppState::Replay
+                | AppState::MatchOver
+        )
+    };
+
     App::new()
         .add_plugins(DefaultPlugins)
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
+        .add_plugin(
+            RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0)
+                .with_default_system_setup(false),
+        )
+        .add_plugin(SimulationPlugin(config.simulation.clone()))
         .add_plugin(DebugLinesPlugin::default())
+        .add_plugin(FrameTimeDiagnosticsPlugin::default())
         .insert_resource(ClearColor(BACKGROUND_COLOR))
+        .add_asset::<Arena>()
+        .add_asset_loader(ArenaLoader)
         .add_loopless_state(AppState::LoadingAssets)
         .add_loading_state(
             LoadingState::new(AppState::LoadingAssets)
                 .continue_to_state(AppState::Setup)
                 .with_collection::<Textures>()
-                .with_collection::<Fonts>(),
+                .with_collection::<Fonts>()
+                .with_collection::<Sounds>()
+                .with_collection::<Arenas>(),
         )
         .add_plugin(RapierDebugRenderPlugin::default())
         .insert_resource(WindowDescriptor {
@@ -62,9 +142,41 @@ fn main() {
         })
         .insert_resource(RapierConfiguration {
             gravity: Vec2::ZERO,
+            timestep_mode: config.simulation.timestep_mode(),
             ..default()
         })
-        .insert_resource(Score { left: 0, right: 0 })
+        .init_resource::<Score>()
+        .insert_resource(ArenaSelection::new(config.table.clone()))
+        .insert_resource(config.table)
+        .insert_resource(config.classification)
+        .insert_resource(config.tracking)
+        .insert_resource(config.stick)
+        .insert_resource(config.zones)
+        .insert_resource(config.players)
+        .insert_resource(config.export)
+        .insert_resource(config.audio)
+        .insert_resource(config.puck)
+        .insert_resource(config.serve)
+        .insert_resource(Match::new(&config.rules))
+        .insert_resource(config.rules)
+        .insert_resource(config.prediction)
+        .insert_resource(config.multi_puck)
+        .insert_resource(config.power_ups)
+        .insert_resource(config.replay)
+        .insert_resource(config.stuck_pucks)
+        .insert_resource(config.score_displays)
+        .insert_resource(arguments)
+        .init_resource::<Tracks>()
+        .init_resource::<Trajectories>()
+        .init_resource::<TrajectoryRecorder>()
+        .init_resource::<PowerUps>()
+        .init_resource::<ReplayBuffer>()
+        .add_event::<LidarScan>()
+        .add_event::<GoalScored>()
+        .add_event::<PuckHitStick>()
+        .add_event::<PuckHitWall>()
+        .add_event::<PuckHitPost>()
+        .add_event::<PlaySound>()
         .add_startup_system(setup_camera)
         .add_system_set(
             ConditionSet::new()
@@ -73,6 +185,7 @@ fn main() {
                 .with_system(setup_ui)
                 .with_system(setup_puck)
                 .with_system(setup_stick)
+                .with_system(setup_tuning_panel)
                 .with_system(|mut commands: Commands| {
                     commands.insert_resource(NextState(AppState::ConnectingToLidar))
                 })
@@ -81,23 +194,180 @@ fn main() {
         .add_enter_system(AppState::ConnectingToLidar, setup_lidar_communication)
         .add_system(wait_for_lidar_messages.run_in_state(AppState::ConnectingToLidar))
         .add_enter_system(AppState::Calibration, lidar_calibration)
+        .add_system(detect_state_key_input)
+        .add_system(update_score_ui)
+        .add_system_to_stage(CoreStage::PostUpdate, despawn_removed_pucks)
+        .add_fixed_timestep_system_set(
+            SIMULATION_STEP,
+            SimulationStage::Gameplay.index(),
+            ConditionSet::new()
+                .run_in_state(AppState::Game(GameState::Running))
+                .with_system(run_serves)
+                .with_system(spawn_additional_pucks)
+                .with_system(update_match_clock)
+                .with_system(spawn_power_ups)
+                .with_system(expire_power_up_effects)
+                .with_system(limit_puck_speed)
+                .with_system(recover_stuck_pucks)
+                .into(),
+        )
+        .add_fixed_timestep_system_set(
+            SIMULATION_STEP,
+            SimulationStage::Collisions.index(),
+            ConditionSet::new()
+                .run_in_state(AppState::Game(GameState::Running))
+                .with_system(detect_goals)
+                .with_system(emit_puck_collision_events)
+                .with_system(track_last_touch)
+                .with_system(collect_power_ups)
+                .with_system(recover_escaped_pucks)
+                .into(),
+        )
+        .add_fixed_timestep_system_set(
+            SIMULATION_STEP,
+            SimulationStage::Score.index(),
+            ConditionSet::new()
+                .run_in_state(AppState::Game(GameState::Running))
+                .with_system(update_score)
+                .with_system(play_collision_sounds)
+                .with_system(play_goal_sounds)
+                .with_system(record_replay_frames)
+                .into(),
+        )
+        .add_fixed_timestep_system(
+            SIMULATION_STEP,
+            SimulationStage::Rules.index(),
+            apply_match_rules
+                .run_in_state(AppState::Game(GameState::Running))
+                .label(MatchSystem::ApplyRules),
+        )
+        // Runs after the rules so that a goal ending the match is not replayed
+        .add_fixed_timestep_system(
+            SIMULATION_STEP,
+            SimulationStage::Rules.index(),
+            start_replay
+                .run_in_state(AppState::Game(GameState::Running))
+                .after(MatchSystem::ApplyRules),
+        )
+        .add_fixed_timestep_system(
+            SIMULATION_STEP,
+            SimulationStage::Rules.index(),
+            advance_simulation_clock.run_if(is_tracking),
+        )
         .add_system_set(
             ConditionSet::new()
-                .run_if(|app_state: Res<CurrentState<AppState>>| {
-                    matches!(app_state.0, AppState::Game(_))
-                })
+                .run_in_state(AppState::Game(GameState::Running))
+                .with_system(update_serve_countdown_ui)
+                .with_system(update_game_time_ui)
+                .with_system(draw_player_zones)
+                .with_system(draw_predicted_paths)
+                .with_system(fade_stuck_indicators)
+                .into(),
+        )
+        .add_system_set(
+            ConditionSet::new()
+                .run_if(is_in_game)
                 .with_system(detect_game_key_input)
-                .with_system(detect_goals)
-                .with_system(handle_lidar_data)
+                .with_system(play_requested_sounds)
+                .with_system(toggle_mute)
+                .with_system(apply_surface_physics)
+                .with_system(update_tuning_panel)
                 .with_system(zoom_camera)
                 .with_system(scale_lidar)
-                .with_system(update_score_ui)
+                .with_system(toggle_tracker_view)
+                .with_system(detect_export_key_input)
                 .into(),
         )
+        .add_enter_system(AppState::Game(GameState::Paused), pause_physics)
+        .add_enter_system(AppState::Game(GameState::Paused), setup_pause_overlay)
+        .add_exit_system(AppState::Game(GameState::Paused), resume_physics)
+        .add_exit_system(AppState::Game(GameState::Paused), cleanup_pause_overlay)
+        .add_enter_system(AppState::Replay, pause_physics)
+        .add_enter_system(AppState::Replay, setup_replay)
+        .add_system(play_replay.run_in_state(AppState::Replay))
+        .add_exit_system(AppState::Replay, resume_physics)
+        .add_exit_system(AppState::Replay, cleanup_replay)
+        .add_enter_system(AppState::MatchOver, setup_match_over_screen)
+        .add_enter_system(AppState::MatchOver, stop_match_recording)
+        .add_system(leave_match_over_screen.run_in_state(AppState::MatchOver))
+        .add_exit_system(AppState::MatchOver, cleanup_match_over_screen)
+        .add_enter_system(AppState::Lobby, setup_lobby)
+        .add_enter_system(AppState::Lobby, setup_arena_label)
+        .add_enter_system(AppState::Lobby, clear_power_ups)
+        .add_enter_system(AppState::Lobby, stop_match_recording)
+        .add_system(start_match_from_lobby.run_in_state(AppState::Lobby))
+        .add_system(select_arena.run_in_state(AppState::Lobby))
+        .add_exit_system(AppState::Lobby, cleanup_lobby)
+        // Leaving the lobby always starts a match
+        .add_exit_system(AppState::Lobby, start_match_recording)
+        .add_fixed_timestep_system(
+            SIMULATION_STEP,
+            SimulationStage::Input.index(),
+            handle_lidar_data
+                .run_if(is_tracking)
+                .label(LidarSystem::ReceiveScan),
+        )
+        .add_fixed_timestep_system(
+            SIMULATION_STEP,
+            SimulationStage::Input.index(),
+            update_tracks
+                .run_if(is_tracking)
+                .label(LidarSystem::UpdateTracks)
+                .after(LidarSystem::ReceiveScan),
+        )
+        .add_fixed_timestep_system(
+            SIMULATION_STEP,
+            SimulationStage::Input.index(),
+            follow_tracks
+                .run_in_state(AppState::Game(GameState::Running))
+                .label(LidarSystem::FollowTracks)
+                .after(LidarSystem::UpdateTracks),
+        )
+        .add_fixed_timestep_system(
+            SIMULATION_STEP,
+            SimulationStage::Input.index(),
+            drive_computer_players
+                .run_in_state(AppState::Game(GameState::Running))
+                .label(LidarSystem::DriveComputers)
+                .after(LidarSystem::FollowTracks),
+        )
+        .add_fixed_timestep_system(
+            SIMULATION_STEP,
+            SimulationStage::Input.index(),
+            // Ordered after every stick moved, so that each step sees the same positions
+            update_stick_presence
+                .run_in_state(AppState::Game(GameState::Running))
+                .after(LidarSystem::DriveComputers),
+        )
+        .add_fixed_timestep_system(
+            SIMULATION_STEP,
+            SimulationStage::Input.index(),
+            record_trajectories
+                .run_if(is_tracking)
+                .after(LidarSystem::UpdateTracks),
+        )
+        .add_fixed_timestep_system(
+            SIMULATION_STEP,
+            SimulationStage::Input.index(),
+            // Ordered after every stick moved, so that the rows of a step show its positions
+            record_trajectories_continuously
+                .run_if(is_tracking)
+                .after(LidarSystem::DriveComputers),
+        )
+        .add_system(drain_lidar_messages.run_if(is_waiting))
+        .add_enter_system(AppState::Tracker, setup_tracker_view)
+        .add_exit_system(AppState::Tracker, cleanup_tracker_view)
         .add_system_set(
             ConditionSet::new()
                 .run_in_state(AppState::Tracker)
-                .with_system(track_object)
+                .with_system(draw_trajectories)
+                .with_system(draw_scan)
+                .with_system(draw_tracks)
+                .with_system(update_tracker_stats)
+                .with_system(toggle_tracker_view)
+                .with_system(detect_export_key_input)
+                .with_system(zoom_camera)
+                .with_system(scale_lidar)
                 .into(),
         )
         .run();
@@ -0,0 +1,193 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    assets::Fonts,
+    puck::Puck,
+    stick::Stick,
+    table::{GoalPost, TableGeometry, Wall},
+};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PuckPhysics {
+    /// Speed in pixels per second the puck is clamped to
+    pub max_speed: f32,
+    /// Linear damping of a slow puck
+    pub base_damping: f32,
+    /// Additional linear damping at `max_speed`, growing quadratically with the speed
+    pub speed_damping: f32,
+    pub angular_damping: f32,
+    /// Friction of the puck, a non-zero value lets glancing hits spin the puck
+    pub friction: f32,
+    pub wall_restitution: f32,
+    pub post_restitution: f32,
+    pub stick_restitution: f32,
+}
+
+impl Default for PuckPhysics {
+    fn default() -> Self {
+        Self {
+            max_speed: 3000.0,
+            base_damping: 0.1,
+            speed_damping: 0.4,
+            angular_damping: 0.2,
+            friction: 0.0,
+            wall_restitution: 0.999,
+            post_restitution: 0.9,
+            stick_restitution: 0.5,
+        }
+    }
+}
+
+const PARAMETER_COUNT: usize = 8;
+
+impl PuckPhysics {
+    /// Name, value and step of every parameter shown in the tuning panel
+    fn parameters(&mut self) -> [(&'static str, &mut f32, f32); PARAMETER_COUNT] {
+        [
+            ("max speed", &mut self.max_speed, 100.0),
+            ("base damping", &mut self.base_damping, 0.05),
+            ("speed damping", &mut self.speed_damping, 0.05),
+            ("angular damping", &mut self.angular_damping, 0.05),
+            ("friction", &mut self.friction, 0.05),
+            ("wall restitution", &mut self.wall_restitution, 0.01),
+            ("post restitution", &mut self.post_restitution, 0.01),
+            ("stick restitution", &mut self.stick_restitution, 0.01),
+        ]
+    }
+}
+
+/// The puck uses the `Max` combine rule with a restitution of zero, so that the restitution of
+/// whatever it hits decides how much it bounces
+pub fn surface_restitution(coefficient: f32) -> Restitution {
+    Restitution {
+        coefficient,
+        combine_rule: CoefficientCombineRule::Max,
+    }
+}
+
+pub fn limit_puck_speed(
+    physics: Res<PuckPhysics>,
+    mut pucks: Query<(&mut Velocity, &mut Damping), With<Puck>>,
+) {
+    for (mut velocity, mut damping) in &mut pucks {
+        let speed = velocity.linvel.length();
+        if speed > physics.max_speed {
+            velocity.linvel *= physics.max_speed / speed;
+        }
+        let relative_speed = (speed / physics.max_speed).min(1.0);
+        damping.linear_damping =
+            physics.base_damping + physics.speed_damping * relative_speed * relative_speed;
+        damping.angular_damping = physics.angular_damping;
+    }
+}
+
+pub fn apply_surface_physics(
+    physics: Res<PuckPhysics>,
+    mut pucks: Query<&mut Friction, With<Puck>>,
+    mut walls: Query<&mut Restitution, (With<Wall>, Without<GoalPost>, Without<Stick>)>,
+    mut posts: Query<&mut Restitution, (With<GoalPost>, Without<Wall>, Without<Stick>)>,
+    mut sticks: Query<&mut Restitution, (With<Stick>, Without<Wall>, Without<GoalPost>)>,
+) {
+    if !physics.is_changed() {
+        return;
+    }
+    for mut friction in &mut pucks {
+        friction.coefficient = physics.friction;
+    }
+    for mut restitution in &mut walls {
+        *restitution = surface_restitution(physics.wall_restitution);
+    }
+    for mut restitution in &mut posts {
+        *restitution = surface_restitution(physics.post_restitution);
+    }
+    for mut restitution in &mut sticks {
+        *restitution = surface_restitution(physics.stick_restitution);
+    }
+}
+
+pub fn pause_physics(mut rapier_configuration: ResMut<RapierConfiguration>) {
+    rapier_configuration.physics_pipeline_active = false;
+}
+
+pub fn resume_physics(mut rapier_configuration: ResMut<RapierConfiguration>) {
+    rapier_configuration.physics_pipeline_active = true;
+}
+
+#[derive(Component)]
+pub struct TuningPanel {
+    selected: usize,
+}
+
+pub fn setup_tuning_panel(mut commands: Commands, fonts: Res<Fonts>, geometry: Res<TableGeometry>) {
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: fonts.arial.clone(),
+                    font_size: 32.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_alignment(TextAlignment::TOP_LEFT),
+            transform: Transform::from_xyz(
+                geometry.length / 2.0 + 80.0,
+                geometry.width / 2.0,
+                10.0,
+            ),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(TuningPanel { selected: 0 });
+}
+
+/// `F2` shows the panel, arrow keys select and change a parameter, `Shift` changes in larger steps
+pub fn update_tuning_panel(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut physics: ResMut<PuckPhysics>,
+    mut panels: Query<(&mut TuningPanel, &mut Text, &mut Visibility)>,
+) {
+    for (mut panel, mut text, mut visibility) in &mut panels {
+        if keyboard_input.just_pressed(KeyCode::F2) {
+            visibility.is_visible = !visibility.is_visible;
+        }
+        if !visibility.is_visible {
+            continue;
+        }
+
+        if keyboard_input.just_pressed(KeyCode::Down) {
+            panel.selected = (panel.selected + 1) % PARAMETER_COUNT;
+        } else if keyboard_input.just_pressed(KeyCode::Up) {
+            panel.selected = (panel.selected + PARAMETER_COUNT - 1) % PARAMETER_COUNT;
+        }
+
+        let direction = if keyboard_input.just_pressed(KeyCode::Right) {
+            1.0
+        } else if keyboard_input.just_pressed(KeyCode::Left) {
+            -1.0
+        } else {
+            0.0
+        };
+        let multiplier = if keyboard_input.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+            10.0
+        } else {
+            1.0
+        };
+        if direction != 0.0 {
+            let mut parameters = physics.parameters();
+            let (_, value, step) = &mut parameters[panel.selected];
+            **value = (**value + direction * multiplier * *step).max(0.0);
+        }
+
+        let mut lines = vec!["Puck physics (F2)".to_string()];
+        let mut current = physics.clone();
+        for (index, (name, value, _)) in current.parameters().into_iter().enumerate() {
+            let marker = if index == panel.selected { ">" } else { " " };
+            lines.push(format!("{marker} {name}: {value:.3}"));
+        }
+        text.sections[0].value = lines.join("\n");
+    }
+}
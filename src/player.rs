@@ -0,0 +1,78 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::ai::Difficulty;
+
+/// Side of the table a team defends, each team scores into the opposite goal
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum Team {
+    Left,
+    Right,
+}
+
+impl Team {
+    pub const ALL: [Team; 2] = [Team::Left, Team::Right];
+
+    pub fn opponent(self) -> Self {
+        match self {
+            Team::Left => Team::Right,
+            Team::Right => Team::Left,
+        }
+    }
+
+    /// Sign of the x coordinates on this team's half of the table
+    pub fn side(self) -> f32 {
+        match self {
+            Team::Left => -1.0,
+            Team::Right => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PlayerSettings {
+    pub name: String,
+    pub team: Team,
+    /// Polygon in table space in which this player's stick may claim tracks, defaults to the
+    /// team's half of the table
+    #[serde(default)]
+    pub zone: Option<Vec<[f32; 2]>>,
+    /// Position of the stick before the player is first tracked, defaults to the team's goal
+    #[serde(default)]
+    pub start: Option<[f32; 2]>,
+    /// Let the computer play this stick instead of following a lidar track
+    #[serde(default)]
+    pub computer: Option<Difficulty>,
+}
+
+impl PlayerSettings {
+    fn new(name: &str, team: Team) -> Self {
+        Self {
+            name: name.to_string(),
+            team,
+            zone: None,
+            start: None,
+            computer: None,
+        }
+    }
+}
+
+/// Players spawned at startup, each gets its own stick
+#[derive(Clone, Debug, Deserialize)]
+#[serde(transparent)]
+pub struct Roster(pub Vec<PlayerSettings>);
+
+impl Default for Roster {
+    fn default() -> Self {
+        Self(vec![
+            PlayerSettings::new("Left", Team::Left),
+            PlayerSettings::new("Right", Team::Right),
+        ])
+    }
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct Player {
+    pub id: usize,
+    pub name: String,
+}
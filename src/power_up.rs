@@ -0,0 +1,383 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+
+use crate::{
+    assets::Textures,
+    collision::LastTouch,
+    physics::{surface_restitution, PuckPhysics},
+    player::Team,
+    puck::{spawn_puck, Puck, RemovedPuck, TemporaryPuck},
+    simulation::{SimulationClock, SimulationRng, SimulationSettings},
+    stick::Stick,
+    table::{TableGeometry, Wall},
+};
+
+/// Side length in pixels of a power-up on the table
+const POWER_UP_SIZE: f32 = 60.0;
+/// Thickness in pixels of the blocks narrowing a goal mouth
+const GOAL_BLOCKER_THICKNESS: f32 = 20.0;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PowerUpSettings {
+    /// Party mode, power-ups appear on the table and apply an effect for the team that last
+    /// touched the puck hitting them
+    pub enabled: bool,
+    /// Seconds between two power-ups appearing
+    pub spawn_interval: f32,
+    pub max_power_ups: usize,
+    /// Seconds an effect lasts
+    pub effect_duration: f32,
+    /// Scale of the sticks of a team with a big stick
+    pub big_stick_scale: f32,
+    /// Share of the goal mouth left open by a shrunken goal
+    pub shrunken_goal: f32,
+    /// Speed of the simulation during slow motion, 1.0 is real time
+    pub slow_motion: f32,
+}
+
+impl Default for PowerUpSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            spawn_interval: 10.0,
+            max_power_ups: 2,
+            effect_duration: 8.0,
+            big_stick_scale: 1.5,
+            shrunken_goal: 0.5,
+            slow_motion: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerUpKind {
+    BigStick,
+    ShrinkGoal,
+    PuckSplit,
+    SlowMotion,
+}
+
+impl PowerUpKind {
+    const ALL: [PowerUpKind; 4] = [
+        PowerUpKind::BigStick,
+        PowerUpKind::ShrinkGoal,
+        PowerUpKind::PuckSplit,
+        PowerUpKind::SlowMotion,
+    ];
+
+    fn color(self) -> Color {
+        match self {
+            PowerUpKind::BigStick => Color::GREEN,
+            PowerUpKind::ShrinkGoal => Color::ORANGE,
+            PowerUpKind::PuckSplit => Color::PURPLE,
+            PowerUpKind::SlowMotion => Color::CYAN,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct PowerUp(pub PowerUpKind);
+
+/// Narrows a shrunken goal mouth, despawned when the effect runs out
+#[derive(Component)]
+pub struct GoalBlocker {
+    /// Team defending the shrunken goal
+    pub team: Team,
+}
+
+struct ActiveEffect {
+    kind: PowerUpKind,
+    /// Team that collected the power-up
+    team: Team,
+    remaining: f32,
+    /// Puck spawned by a puck split
+    puck: Option<Entity>,
+}
+
+#[derive(Default)]
+pub struct PowerUps {
+    effects: Vec<ActiveEffect>,
+    since_last_spawn: f32,
+}
+
+pub fn spawn_power_ups(
+    mut commands: Commands,
+    clock: Res<SimulationClock>,
+    settings: Res<PowerUpSettings>,
+    geometry: Res<TableGeometry>,
+    mut power_ups: ResMut<PowerUps>,
+    mut rng: ResMut<SimulationRng>,
+    existing: Query<(), With<PowerUp>>,
+) {
+    if !settings.enabled || existing.iter().count() >= settings.max_power_ups {
+        power_ups.since_last_spawn = 0.0;
+        return;
+    }
+    power_ups.since_last_spawn += clock.delta_seconds();
+    if power_ups.since_last_spawn < settings.spawn_interval {
+        return;
+    }
+    power_ups.since_last_spawn = 0.0;
+
+    // Keep power-ups away from the goals and the rails, where they could not be reached
+    let position = Vec2::new(
+        rng.0.gen_range(-0.25..=0.25) * geometry.length,
+        rng.0.gen_range(-0.33..=0.33) * geometry.width,
+    );
+    let kind = *PowerUpKind::ALL
+        .choose(&mut rng.0)
+        .expect("there are power-up kinds");
+    info!("Spawning power-up {:?}", kind);
+    commands
+        .spawn()
+        .insert(PowerUp(kind))
+        .insert(Collider::ball(POWER_UP_SIZE / 2.0))
+        .insert(Sensor)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: kind.color(),
+                custom_size: Some(Vec2::splat(POWER_UP_SIZE)),
+                ..default()
+            },
+            transform: Transform::from_translation(position.extend(1.5))
+                .with_rotation(Quat::from_rotation_z(PI / 4.0)),
+            ..default()
+        });
+}
+
+pub fn collect_power_ups(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    settings: Res<PowerUpSettings>,
+    textures: Res<Textures>,
+    geometry: Res<TableGeometry>,
+    physics: Res<PuckPhysics>,
+    mut power_ups: ResMut<PowerUps>,
+    simulation: Res<SimulationSettings>,
+    mut rapier_configuration: ResMut<RapierConfiguration>,
+    pickups: Query<&PowerUp>,
+    pucks: Query<(&LastTouch, &Transform, &Velocity), With<Puck>>,
+    mut sticks: Query<(&Team, &mut Transform), (With<Stick>, Without<Puck>)>,
+) {
+    for collision_event in collision_events.iter() {
+        let (first, second) = match *collision_event {
+            CollisionEvent::Started(first, second, _) => (first, second),
+            CollisionEvent::Stopped(..) => continue,
+        };
+        let (pickup, puck) = if pickups.contains(first) {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        let (kind, (last_touch, puck_transform, puck_velocity)) =
+            match (pickups.get(pickup), pucks.get(puck)) {
+                (Ok(power_up), Ok(puck)) => (power_up.0, puck),
+                _ => continue,
+            };
+        // Power-ups only count after a player touched the puck
+        let team = match last_touch.0 {
+            Some((team, _)) => team,
+            None => continue,
+        };
+        commands.entity(pickup).despawn_recursive();
+
+        if let Some(effect) = power_ups
+            .effects
+            .iter_mut()
+            .find(|effect| effect.kind == kind && effect.team == team && effect.puck.is_none())
+        {
+            info!("{:?} refreshed for {:?}", kind, team);
+            effect.remaining = settings.effect_duration;
+            continue;
+        }
+        info!("{:?} collected by {:?}", kind, team);
+
+        let mut split_puck = None;
+        match kind {
+            PowerUpKind::BigStick => {
+                for (_, mut transform) in sticks
+                    .iter_mut()
+                    .filter(|(stick_team, _)| **stick_team == team)
+                {
+                    transform.scale =
+                        Vec3::new(settings.big_stick_scale, settings.big_stick_scale, 1.0);
+                }
+            }
+            PowerUpKind::ShrinkGoal => {
+                spawn_goal_blockers(
+                    &mut commands,
+                    team.opponent(),
+                    &settings,
+                    &geometry,
+                    &physics,
+                );
+            }
+            PowerUpKind::PuckSplit => {
+                let position = puck_transform.translation.truncate();
+                let velocity = Mat2::from_angle(PI / 6.0) * puck_velocity.linvel;
+                let offset = velocity.normalize_or_zero().perp() * geometry.puck_diameter;
+                let puck = spawn_puck(
+                    &mut commands,
+                    &textures,
+                    &geometry,
+                    &physics,
+                    position + offset,
+                );
+                commands
+                    .entity(puck)
+                    .insert(TemporaryPuck)
+                    .insert(LastTouch(last_touch.0))
+                    .insert(Velocity::linear(velocity));
+                split_puck = Some(puck);
+            }
+            PowerUpKind::SlowMotion => {
+                set_time_scale(&mut rapier_configuration, &simulation, settings.slow_motion);
+            }
+        }
+        power_ups.effects.push(ActiveEffect {
+            kind,
+            team,
+            remaining: settings.effect_duration,
+            puck: split_puck,
+        });
+    }
+}
+
+/// Slows down physics by shortening the fixed physics step, gameplay timers keep running in real
+/// time
+fn set_time_scale(
+    rapier_configuration: &mut RapierConfiguration,
+    simulation: &SimulationSettings,
+    scale: f32,
+) {
+    match &mut rapier_configuration.timestep_mode {
+        TimestepMode::Fixed { dt, .. } => *dt = simulation.step() * scale,
+        TimestepMode::Variable { time_scale, .. } => *time_scale = scale,
+        _ => {}
+    }
+}
+
+/// Blocks both outer parts of the goal mouth of `team`, leaving the middle open
+fn spawn_goal_blockers(
+    commands: &mut Commands,
+    team: Team,
+    settings: &PowerUpSettings,
+    geometry: &TableGeometry,
+    physics: &PuckPhysics,
+) {
+    let blocked_length = geometry.goal_width * (1.0 - settings.shrunken_goal) / 2.0;
+    for side in [-1.0, 1.0] {
+        let position = geometry.goal_center(team)
+            + Vec2::new(
+                team.side() * GOAL_BLOCKER_THICKNESS / 2.0,
+                side * (geometry.goal_width - blocked_length) / 2.0,
+            );
+        commands
+            .spawn()
+            .insert(GoalBlocker { team })
+            .insert(Wall)
+            .insert(RigidBody::Fixed)
+            .insert(Collider::cuboid(
+                GOAL_BLOCKER_THICKNESS / 2.0,
+                blocked_length / 2.0,
+            ))
+            .insert(surface_restitution(physics.wall_restitution))
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: PowerUpKind::ShrinkGoal.color(),
+                    custom_size: Some(Vec2::new(GOAL_BLOCKER_THICKNESS, blocked_length)),
+                    ..default()
+                },
+                transform: Transform::from_translation(position.extend(1.0)),
+                ..default()
+            });
+    }
+}
+
+pub fn expire_power_up_effects(
+    mut commands: Commands,
+    clock: Res<SimulationClock>,
+    mut power_ups: ResMut<PowerUps>,
+    simulation: Res<SimulationSettings>,
+    mut rapier_configuration: ResMut<RapierConfiguration>,
+    mut sticks: Query<(&Team, &mut Transform), With<Stick>>,
+    blockers: Query<(Entity, &GoalBlocker)>,
+    pucks: Query<(), (With<Puck>, Without<RemovedPuck>)>,
+) {
+    for effect in &mut power_ups.effects {
+        effect.remaining -= clock.delta_seconds();
+    }
+    let (expired, active): (Vec<_>, Vec<_>) = power_ups
+        .effects
+        .drain(..)
+        .partition(|effect| effect.remaining <= 0.0);
+    power_ups.effects = active;
+
+    let mut puck_count = pucks.iter().count();
+    for effect in expired {
+        info!("{:?} of {:?} ran out", effect.kind, effect.team);
+        match effect.kind {
+            PowerUpKind::BigStick => {
+                for (_, mut transform) in
+                    sticks.iter_mut().filter(|(team, _)| **team == effect.team)
+                {
+                    transform.scale = Vec3::ONE;
+                }
+            }
+            PowerUpKind::ShrinkGoal => {
+                for (entity, _) in blockers
+                    .iter()
+                    .filter(|(_, blocker)| blocker.team == effect.team.opponent())
+                {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
+            PowerUpKind::PuckSplit => {
+                // The split puck may have scored and been removed already, and stays as an
+                // ordinary puck if it is the last one in play
+                if let Some(puck) = effect.puck.filter(|&puck| pucks.contains(puck)) {
+                    if puck_count > 1 {
+                        commands.entity(puck).insert(RemovedPuck);
+                        puck_count -= 1;
+                    } else {
+                        commands.entity(puck).remove::<TemporaryPuck>();
+                    }
+                }
+            }
+            PowerUpKind::SlowMotion => {
+                let still_slowed = power_ups
+                    .effects
+                    .iter()
+                    .any(|effect| effect.kind == PowerUpKind::SlowMotion);
+                if !still_slowed {
+                    set_time_scale(&mut rapier_configuration, &simulation, 1.0);
+                }
+            }
+        }
+    }
+}
+
+/// Ends every effect and removes the power-ups on the table, so that no effect carries over into
+/// the next match
+pub fn clear_power_ups(
+    mut commands: Commands,
+    mut power_ups: ResMut<PowerUps>,
+    simulation: Res<SimulationSettings>,
+    mut rapier_configuration: ResMut<RapierConfiguration>,
+    mut sticks: Query<&mut Transform, With<Stick>>,
+    entities: Query<Entity, Or<(With<PowerUp>, With<GoalBlocker>)>>,
+) {
+    *power_ups = PowerUps::default();
+    set_time_scale(&mut rapier_configuration, &simulation, 1.0);
+    for mut transform in &mut sticks {
+        transform.scale = Vec3::ONE;
+    }
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
+}
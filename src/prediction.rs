@@ -0,0 +1,242 @@
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::DebugLines;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    physics::PuckPhysics,
+    player::Team,
+    puck::Puck,
+    serve::Serve,
+    table::{closest_point_on_segment, TableGeometry},
+};
+
+/// Seconds between two simulated positions, short enough that a puck at full speed cannot skip
+/// through a rail
+const PREDICTION_STEP: f32 = 1.0 / 120.0;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PredictionSettings {
+    /// Seconds of the puck's future path that are predicted and drawn
+    pub duration: f32,
+    /// Draw the predicted path, toggled with `P`
+    pub show: bool,
+}
+
+impl Default for PredictionSettings {
+    fn default() -> Self {
+        Self {
+            duration: 1.0,
+            show: false,
+        }
+    }
+}
+
+pub struct PredictedPath {
+    /// Seconds from now and position of the puck's center
+    pub points: Vec<(f32, Vec2)>,
+    /// Team defending the goal the puck enters and seconds until it does
+    pub goal: Option<(Team, f32)>,
+}
+
+impl PredictedPath {
+    /// First predicted position at which the puck crosses the line at `x`
+    pub fn crossing(&self, x: f32) -> Option<(f32, Vec2)> {
+        self.points.windows(2).find_map(|pair| {
+            let ((start_time, start), (end_time, end)) = (pair[0], pair[1]);
+            if (start.x - x) * (end.x - x) > 0.0 || start.x == end.x {
+                return None;
+            }
+            let fraction = (x - start.x) / (end.x - start.x);
+            Some((
+                start_time + fraction * (end_time - start_time),
+                start.lerp(end, fraction),
+            ))
+        })
+    }
+}
+
+/// Simulates a free puck against the rails and goal posts of the table, ignoring sticks
+pub struct PuckPredictor<'a> {
+    geometry: &'a TableGeometry,
+    physics: &'a PuckPhysics,
+    walls: Vec<(Vec2, Vec2)>,
+    posts: Vec<Vec2>,
+}
+
+impl<'a> PuckPredictor<'a> {
+    pub fn new(geometry: &'a TableGeometry, physics: &'a PuckPhysics) -> Self {
+        let walls = geometry
+            .walls()
+            .iter()
+            .flat_map(|wall| wall.windows(2).map(|segment| (segment[0], segment[1])))
+            .collect();
+        let posts = geometry
+            .goal_posts()
+            .into_iter()
+            .map(|(_, position)| position)
+            .collect();
+        Self {
+            geometry,
+            physics,
+            walls,
+            posts,
+        }
+    }
+
+    pub fn predict(&self, mut position: Vec2, mut velocity: Vec2, duration: f32) -> PredictedPath {
+        let puck_radius = self.geometry.puck_diameter / 2.0;
+        let post_radius = self.geometry.goal_post_diameter / 2.0;
+        let goal_line = self.geometry.length / 2.0 + puck_radius;
+
+        let mut points = vec![(0.0, position)];
+        let mut time = 0.0;
+        while time < duration {
+            time += PREDICTION_STEP;
+            // Same damping curve as `limit_puck_speed`, integrated the way rapier does
+            let speed = velocity.length().min(self.physics.max_speed);
+            let relative_speed = speed / self.physics.max_speed;
+            let damping = self.physics.base_damping
+                + self.physics.speed_damping * relative_speed * relative_speed;
+            velocity = velocity.clamp_length_max(self.physics.max_speed)
+                / (1.0 + PREDICTION_STEP * damping);
+            position += velocity * PREDICTION_STEP;
+
+            for &(start, end) in &self.walls {
+                let closest = closest_point_on_segment(position, start, end);
+                bounce(
+                    &mut position,
+                    &mut velocity,
+                    closest,
+                    puck_radius,
+                    self.physics.wall_restitution,
+                );
+            }
+            for &post in &self.posts {
+                let offset = position - post;
+                let closest = post + offset.normalize_or_zero() * post_radius;
+                bounce(
+                    &mut position,
+                    &mut velocity,
+                    closest,
+                    puck_radius,
+                    self.physics.post_restitution,
+                );
+            }
+            points.push((time, position));
+
+            if position.x.abs() > goal_line {
+                let team = if position.x < 0.0 {
+                    Team::Left
+                } else {
+                    Team::Right
+                };
+                return PredictedPath {
+                    points,
+                    goal: Some((team, time)),
+                };
+            }
+            if velocity.length() < 1.0 {
+                break;
+            }
+        }
+        PredictedPath { points, goal: None }
+    }
+}
+
+/// Pushes the puck out of a surface it overlaps at `contact` and reflects its velocity
+fn bounce(position: &mut Vec2, velocity: &mut Vec2, contact: Vec2, radius: f32, restitution: f32) {
+    let offset = *position - contact;
+    let distance = offset.length();
+    if distance >= radius || distance <= f32::EPSILON {
+        return;
+    }
+    let normal = offset / distance;
+    *position = contact + normal * radius;
+    let normal_speed = velocity.dot(normal);
+    if normal_speed < 0.0 {
+        *velocity -= (1.0 + restitution) * normal_speed * normal;
+    }
+}
+
+/// Draws each free puck's predicted path, fading out towards the end of the prediction
+pub fn draw_predicted_paths(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut settings: ResMut<PredictionSettings>,
+    geometry: Res<TableGeometry>,
+    physics: Res<PuckPhysics>,
+    pucks: Query<(&Transform, &Velocity), (With<Puck>, Without<Serve>)>,
+    mut lines: ResMut<DebugLines>,
+) {
+    if keyboard_input.just_pressed(KeyCode::P) {
+        settings.show = !settings.show;
+    }
+    if !settings.show {
+        return;
+    }
+
+    let predictor = PuckPredictor::new(&geometry, &physics);
+    for (transform, velocity) in &pucks {
+        let path = predictor.predict(
+            transform.translation.truncate(),
+            velocity.linvel,
+            settings.duration,
+        );
+        let color = if path.goal.is_some() {
+            Color::RED
+        } else {
+            Color::YELLOW
+        };
+        for pair in path.points.windows(2) {
+            let ((_, start), (end_time, end)) = (pair[0], pair[1]);
+            let mut faded = color;
+            faded.set_a(1.0 - end_time / settings.duration);
+            lines.line_colored(start.extend(3.0), end.extend(3.0), 0.0, faded);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crossing_interpolates_between_predicted_points() {
+        let path = PredictedPath {
+            points: vec![
+                (0.0, Vec2::new(0.0, 0.0)),
+                (0.1, Vec2::new(100.0, 50.0)),
+                (0.2, Vec2::new(200.0, 100.0)),
+            ],
+            goal: None,
+        };
+        let (time, position) = path.crossing(150.0).unwrap();
+        assert!((time - 0.15).abs() < 1e-6);
+        assert!(position.distance(Vec2::new(150.0, 75.0)) < 1e-3);
+        assert!(path.crossing(300.0).is_none());
+        assert!(path.crossing(-10.0).is_none());
+    }
+
+    #[test]
+    fn predicted_puck_bounces_off_rail() {
+        let geometry = TableGeometry::default();
+        let physics = PuckPhysics::default();
+        let predictor = PuckPredictor::new(&geometry, &physics);
+        // Without the upper rail the puck would be far above the table at x = 450
+        let path = predictor.predict(Vec2::ZERO, Vec2::new(600.0, 1200.0), 1.0);
+        let (_, position) = path.crossing(450.0).unwrap();
+        let highest = geometry.width / 2.0 - geometry.puck_diameter / 2.0;
+        assert!(position.y <= highest + 1.0, "crossed at {}", position);
+        assert!(path.goal.is_none());
+    }
+
+    #[test]
+    fn predicted_puck_enters_goal() {
+        let geometry = TableGeometry::default();
+        let physics = PuckPhysics::default();
+        let predictor = PuckPredictor::new(&geometry, &physics);
+        let path = predictor.predict(Vec2::ZERO, Vec2::new(-2000.0, 0.0), 2.0);
+        assert!(matches!(path.goal, Some((Team::Left, _))));
+    }
+}
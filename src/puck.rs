@@ -1,35 +1,124 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
 
-use crate::{assets::Textures, PUCK_DIAMETER};
+use crate::{
+    assets::Textures,
+    collision::LastTouch,
+    physics::{surface_restitution, PuckPhysics},
+    serve::{start_serve, ServeSettings},
+    simulation::SimulationClock,
+    stuck::StuckTime,
+    table::TableGeometry,
+};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MultiPuckSettings {
+    /// Spawn additional pucks over time, a puck that scored is removed while others are left
+    pub enabled: bool,
+    pub max_pucks: usize,
+    /// Seconds between two additional pucks
+    pub spawn_interval: f32,
+}
+
+impl Default for MultiPuckSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_pucks: 3,
+            spawn_interval: 20.0,
+        }
+    }
+}
 
 #[derive(Component)]
 pub struct Puck;
 
-pub fn setup_puck(mut commands: Commands, textures: Res<Textures>) {
+/// A puck that is despawned at the end of the frame, after every system that may still add
+/// components to it ran
+#[derive(Component)]
+pub struct RemovedPuck;
+
+/// A puck that only exists for a while, it is removed instead of served when it scores
+#[derive(Component)]
+pub struct TemporaryPuck;
+
+pub fn spawn_puck(
+    commands: &mut Commands,
+    textures: &Textures,
+    geometry: &TableGeometry,
+    physics: &PuckPhysics,
+    position: Vec2,
+) -> Entity {
     commands
         .spawn()
         .insert(Puck)
+        .insert(LastTouch::default())
+        .insert(StuckTime::default())
         .insert(RigidBody::Dynamic)
-        .insert(Collider::ball(PUCK_DIAMETER / 2.0))
+        .insert(Collider::ball(geometry.puck_diameter / 2.0))
         .insert(Damping {
-            linear_damping: 0.1,
-            angular_damping: 0.2,
+            linear_damping: physics.base_damping,
+            angular_damping: physics.angular_damping,
         })
-        .insert(Friction::coefficient(0.0))
-        .insert(Restitution::coefficient(0.999))
+        .insert(Friction::coefficient(physics.friction))
+        .insert(surface_restitution(0.0))
         .insert(Velocity::zero())
+        .insert(ActiveEvents::COLLISION_EVENTS)
         //.insert(LockedAxes::ROTATION_LOCKED)
         .insert_bundle(SpriteBundle {
             sprite: Sprite {
-                custom_size: Some(Vec2::new(PUCK_DIAMETER, PUCK_DIAMETER)),
+                custom_size: Some(Vec2::new(geometry.puck_diameter, geometry.puck_diameter)),
                 ..default()
             },
             texture: textures.puck.clone(),
             ..default()
         })
-        .insert_bundle(TransformBundle::from(Transform::from_xyz(
-            100.0, 100.0, 2.0,
+        .insert_bundle(TransformBundle::from(Transform::from_translation(
+            position.extend(2.0),
         )))
-        .insert(Ccd::enabled());
+        .insert(Ccd::enabled())
+        .id()
+}
+
+pub fn setup_puck(
+    mut commands: Commands,
+    textures: Res<Textures>,
+    geometry: Res<TableGeometry>,
+    physics: Res<PuckPhysics>,
+    serve_settings: Res<ServeSettings>,
+) {
+    let puck = spawn_puck(&mut commands, &textures, &geometry, &physics, Vec2::ZERO);
+    start_serve(&mut commands, puck, None, &serve_settings);
+}
+
+pub fn spawn_additional_pucks(
+    mut commands: Commands,
+    clock: Res<SimulationClock>,
+    settings: Res<MultiPuckSettings>,
+    textures: Res<Textures>,
+    geometry: Res<TableGeometry>,
+    physics: Res<PuckPhysics>,
+    serve_settings: Res<ServeSettings>,
+    pucks: Query<(), (With<Puck>, Without<RemovedPuck>)>,
+    mut since_last_spawn: Local<f32>,
+) {
+    if !settings.enabled || pucks.iter().count() >= settings.max_pucks {
+        *since_last_spawn = 0.0;
+        return;
+    }
+    *since_last_spawn += clock.delta_seconds();
+    if *since_last_spawn >= settings.spawn_interval {
+        *since_last_spawn = 0.0;
+        info!("Spawning an additional puck");
+        let puck = spawn_puck(&mut commands, &textures, &geometry, &physics, Vec2::ZERO);
+        start_serve(&mut commands, puck, None, &serve_settings);
+    }
+}
+
+pub fn despawn_removed_pucks(mut commands: Commands, pucks: Query<Entity, With<RemovedPuck>>) {
+    for puck in &pucks {
+        commands.entity(puck).despawn_recursive();
+    }
 }
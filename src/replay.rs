@@ -0,0 +1,240 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    app_state::{AppState, GameState},
+    assets::{Fonts, Textures},
+    puck::{Puck, RemovedPuck},
+    rules::Match,
+    score::GoalScored,
+    simulation::SimulationClock,
+    stick::Stick,
+    table::TableGeometry,
+};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ReplaySettings {
+    /// Show a replay after each goal that does not end the match
+    pub enabled: bool,
+    /// Seconds before the goal that are replayed
+    pub duration: f64,
+    /// Playback speed, 1.0 is real time
+    pub speed: f32,
+}
+
+impl Default for ReplaySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            duration: 4.0,
+            speed: 0.4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ReplayKind {
+    Puck,
+    Stick,
+}
+
+struct ReplayFrame {
+    time: f64,
+    transforms: Vec<(Entity, ReplayKind, Transform)>,
+}
+
+/// Transforms of the pucks and sticks over the last seconds of play
+#[derive(Default)]
+pub struct ReplayBuffer {
+    frames: VecDeque<ReplayFrame>,
+    /// Seconds of the recording played back so far
+    elapsed: f64,
+}
+
+/// Stand-in drawn for a recorded puck or stick while the live ones are hidden
+#[derive(Component)]
+pub struct ReplayGhost(Entity);
+
+/// Marks every entity spawned for the replay, despawned when the match resumes
+#[derive(Component)]
+pub struct ReplayUi;
+
+pub fn record_replay_frames(
+    clock: Res<SimulationClock>,
+    settings: Res<ReplaySettings>,
+    mut buffer: ResMut<ReplayBuffer>,
+    pucks: Query<(Entity, &Transform), (With<Puck>, Without<RemovedPuck>)>,
+    sticks: Query<(Entity, &Transform), With<Stick>>,
+) {
+    if !settings.enabled {
+        return;
+    }
+    let now = clock.seconds_since_startup();
+    let transforms = pucks
+        .iter()
+        .map(|(entity, transform)| (entity, ReplayKind::Puck, *transform))
+        .chain(
+            sticks
+                .iter()
+                .map(|(entity, transform)| (entity, ReplayKind::Stick, *transform)),
+        )
+        .collect();
+    buffer.frames.push_back(ReplayFrame {
+        time: now,
+        transforms,
+    });
+    while buffer
+        .frames
+        .front()
+        .map_or(false, |frame| now - frame.time > settings.duration)
+    {
+        buffer.frames.pop_front();
+    }
+}
+
+/// Switches to the replay after a goal, unless the goal ended the match
+pub fn start_replay(
+    mut commands: Commands,
+    settings: Res<ReplaySettings>,
+    game: Res<Match>,
+    buffer: Res<ReplayBuffer>,
+    mut goal_events: EventReader<GoalScored>,
+) {
+    if goal_events.iter().count() == 0 {
+        return;
+    }
+    if settings.enabled && game.result.is_none() && buffer.frames.len() > 1 {
+        commands.insert_resource(NextState(AppState::Replay));
+    }
+}
+
+pub fn setup_replay(
+    mut commands: Commands,
+    textures: Res<Textures>,
+    fonts: Res<Fonts>,
+    geometry: Res<TableGeometry>,
+    mut buffer: ResMut<ReplayBuffer>,
+    mut live: Query<&mut Visibility, Or<(With<Puck>, With<Stick>)>>,
+) {
+    info!("Replaying the last {} frames", buffer.frames.len());
+    buffer.elapsed = 0.0;
+    for mut visibility in &mut live {
+        visibility.is_visible = false;
+    }
+
+    let mut ghosts: Vec<(Entity, ReplayKind)> = Vec::new();
+    for &(entity, kind, _) in buffer.frames.iter().flat_map(|frame| &frame.transforms) {
+        if !ghosts.iter().any(|&(ghost, _)| ghost == entity) {
+            ghosts.push((entity, kind));
+        }
+    }
+    for (entity, kind) in ghosts {
+        let (texture, diameter) = match kind {
+            ReplayKind::Puck => (textures.puck.clone(), geometry.puck_diameter),
+            ReplayKind::Stick => (textures.stick.clone(), geometry.stick_diameter),
+        };
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    custom_size: Some(Vec2::splat(diameter)),
+                    ..default()
+                },
+                texture,
+                visibility: Visibility { is_visible: false },
+                ..default()
+            })
+            .insert(ReplayGhost(entity))
+            .insert(ReplayUi);
+    }
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                "Replay",
+                TextStyle {
+                    font: fonts.arial.clone(),
+                    font_size: 80.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_alignment(TextAlignment::BOTTOM_CENTER),
+            transform: Transform::from_xyz(0.0, geometry.width / 2.0 - 100.0, 20.0),
+            ..default()
+        })
+        .insert(ReplayUi);
+}
+
+/// Moves the ghosts along the recording in slow motion, Enter skips the rest of the replay
+pub fn play_replay(
+    mut commands: Commands,
+    time: Res<Time>,
+    keyboard_input: Res<Input<KeyCode>>,
+    settings: Res<ReplaySettings>,
+    mut buffer: ResMut<ReplayBuffer>,
+    mut ghosts: Query<(&ReplayGhost, &mut Transform, &mut Visibility)>,
+) {
+    buffer.elapsed += time.delta_seconds_f64() * settings.speed as f64;
+    let (first, last) = match (buffer.frames.front(), buffer.frames.back()) {
+        (Some(first), Some(last)) => (first.time, last.time),
+        _ => return,
+    };
+    let now = first + buffer.elapsed;
+    if now >= last || keyboard_input.just_pressed(KeyCode::Return) {
+        commands.insert_resource(NextState(AppState::Game(GameState::Running)));
+        return;
+    }
+
+    let next = buffer
+        .frames
+        .iter()
+        .position(|frame| frame.time > now)
+        .unwrap_or(buffer.frames.len() - 1);
+    let (previous, next) = (&buffer.frames[next.saturating_sub(1)], &buffer.frames[next]);
+    let fraction = if next.time > previous.time {
+        ((now - previous.time) / (next.time - previous.time)) as f32
+    } else {
+        1.0
+    };
+
+    for (ghost, mut transform, mut visibility) in &mut ghosts {
+        let find = |frame: &ReplayFrame| {
+            frame
+                .transforms
+                .iter()
+                .find(|(entity, _, _)| *entity == ghost.0)
+                .map(|&(_, _, transform)| transform)
+        };
+        let interpolated = match (find(previous), find(next)) {
+            (Some(start), Some(end)) => Some(Transform {
+                translation: start.translation.lerp(end.translation, fraction),
+                rotation: start.rotation.slerp(end.rotation, fraction),
+                scale: start.scale.lerp(end.scale, fraction),
+            }),
+            (start, end) => start.or(end),
+        };
+        visibility.is_visible = interpolated.is_some();
+        if let Some(interpolated) = interpolated {
+            *transform = interpolated;
+        }
+    }
+}
+
+pub fn cleanup_replay(
+    mut commands: Commands,
+    mut buffer: ResMut<ReplayBuffer>,
+    entities: Query<Entity, With<ReplayUi>>,
+    mut live: Query<&mut Visibility, (Or<(With<Puck>, With<Stick>)>, Without<ReplayUi>)>,
+) {
+    // The next replay only shows what happened after this goal
+    buffer.frames.clear();
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
+    for mut visibility in &mut live {
+        visibility.is_visible = true;
+    }
+}
@@ -0,0 +1,516 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use iyes_loopless::state::NextState;
+use serde::Deserialize;
+
+use crate::{
+    app_state::{AppState, GameState},
+    assets::Fonts,
+    audio::{PlaySound, SoundEffect},
+    player::{Player, Team},
+    puck::{Puck, RemovedPuck},
+    score::Score,
+    serve::{start_serve, ServeSettings},
+    simulation::SimulationClock,
+};
+
+/// Seconds before the end of a timed period in which every second is announced
+const CLOCK_WARNING_SECONDS: f32 = 3.0;
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum MatchFormat {
+    /// The first team to score `goals` wins
+    FirstTo { goals: usize },
+    /// The team with more goals after all halves wins, a tie goes to overtime
+    TimedHalves { halves: usize, half_duration: f32 },
+    /// A set is won by the first team to score `goals_per_set`, the first team to win more than
+    /// half of the `sets` wins the match
+    BestOfSets { sets: usize, goals_per_set: usize },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MatchSettings {
+    pub format: MatchFormat,
+    /// Whether a tied timed match is decided by the next goal, otherwise it ends in a draw
+    pub sudden_death: bool,
+    /// Seconds the winner is shown before returning to the lobby
+    pub result_duration: f32,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        Self {
+            format: MatchFormat::FirstTo { goals: 7 },
+            sudden_death: true,
+            result_duration: 8.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchResult {
+    Winner(Team),
+    Draw,
+}
+
+/// Progress of the running match, the goals of the current half or set are kept in `Score`
+#[derive(Debug)]
+pub struct Match {
+    /// Index of the current half or set
+    pub period: usize,
+    /// Seconds left in a timed period
+    pub remaining: Option<f32>,
+    pub sets: HashMap<Team, usize>,
+    /// The next goal decides the match
+    pub sudden_death: bool,
+    pub result: Option<MatchResult>,
+}
+
+impl Match {
+    pub fn new(settings: &MatchSettings) -> Self {
+        let remaining = match settings.format {
+            MatchFormat::TimedHalves { half_duration, .. } => Some(half_duration),
+            _ => None,
+        };
+        Self {
+            period: 0,
+            remaining,
+            sets: HashMap::new(),
+            sudden_death: false,
+            result: None,
+        }
+    }
+
+    pub fn sets(&self, team: Team) -> usize {
+        self.sets.get(&team).copied().unwrap_or_default()
+    }
+
+    /// Short description of the current period for the clock display
+    pub fn period_label(&self, settings: &MatchSettings) -> String {
+        if self.sudden_death {
+            return "Sudden death".to_string();
+        }
+        match settings.format {
+            MatchFormat::FirstTo { goals } => format!("First to {goals}"),
+            MatchFormat::TimedHalves { halves, .. } => {
+                format!("Half {}/{}", self.period + 1, halves)
+            }
+            MatchFormat::BestOfSets { sets, .. } => format!(
+                "Set {}/{} ({}:{})",
+                self.period + 1,
+                sets,
+                self.sets(Team::Left),
+                self.sets(Team::Right)
+            ),
+        }
+    }
+}
+
+fn leading_team(score: &Score) -> Option<Team> {
+    let (left, right) = (score.goals(Team::Left), score.goals(Team::Right));
+    match left.cmp(&right) {
+        std::cmp::Ordering::Greater => Some(Team::Left),
+        std::cmp::Ordering::Less => Some(Team::Right),
+        std::cmp::Ordering::Equal => None,
+    }
+}
+
+/// Resets score and match and serves a faceoff with a single puck, used to start a match from the
+/// lobby and by `R`
+pub fn restart_match(
+    commands: &mut Commands,
+    settings: &MatchSettings,
+    serve_settings: &ServeSettings,
+    pucks: impl IntoIterator<Item = Entity>,
+) {
+    commands.insert_resource(Score::default());
+    commands.insert_resource(Match::new(settings));
+    let mut pucks = pucks.into_iter();
+    if let Some(puck) = pucks.next() {
+        start_serve(commands, puck, None, serve_settings);
+    }
+    for puck in pucks {
+        commands.entity(puck).insert(RemovedPuck);
+    }
+}
+
+fn end_match(
+    commands: &mut Commands,
+    game: &mut Match,
+    result: MatchResult,
+    sounds: &mut EventWriter<PlaySound>,
+) {
+    info!("Match over: {:?}", result);
+    game.result = Some(result);
+    sounds.send(PlaySound(SoundEffect::MatchEnd));
+    commands.insert_resource(NextState(AppState::MatchOver));
+}
+
+pub fn update_match_clock(
+    clock: Res<SimulationClock>,
+    mut game: ResMut<Match>,
+    mut sounds: EventWriter<PlaySound>,
+) {
+    if let Some(remaining) = &mut game.remaining {
+        let previous = *remaining;
+        *remaining = (*remaining - clock.delta_seconds()).max(0.0);
+        if previous.ceil() != remaining.ceil()
+            && previous <= CLOCK_WARNING_SECONDS
+            && *remaining > 0.0
+        {
+            sounds.send(PlaySound(SoundEffect::Countdown));
+        }
+    }
+}
+
+/// What the rules demand after a step, besides the progress already recorded in `Match`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RuleOutcome {
+    /// A set was won without deciding the match, the next one starts from zero
+    NextSet,
+    NextHalf,
+    /// Tied after regular time, the next goal decides
+    SuddenDeath,
+    End(MatchResult),
+}
+
+/// Moves `game` on to the next half or set and decides the match once the score or the clock
+/// demand it
+fn apply_rules(game: &mut Match, score: &Score, settings: &MatchSettings) -> Option<RuleOutcome> {
+    if game.result.is_some() {
+        return None;
+    }
+    // Sudden death starts tied, so any lead decides the match
+    if game.sudden_death {
+        return leading_team(score).map(|team| RuleOutcome::End(MatchResult::Winner(team)));
+    }
+
+    match settings.format {
+        MatchFormat::FirstTo { goals } => Team::ALL
+            .into_iter()
+            .find(|&team| score.goals(team) >= goals)
+            .map(|team| RuleOutcome::End(MatchResult::Winner(team))),
+        MatchFormat::BestOfSets {
+            sets,
+            goals_per_set,
+        } => {
+            let team = Team::ALL
+                .into_iter()
+                .find(|&team| score.goals(team) >= goals_per_set)?;
+            *game.sets.entry(team).or_default() += 1;
+            if game.sets(team) > sets / 2 {
+                Some(RuleOutcome::End(MatchResult::Winner(team)))
+            } else {
+                info!("Set {} won by {:?}", game.period + 1, team);
+                game.period += 1;
+                Some(RuleOutcome::NextSet)
+            }
+        }
+        MatchFormat::TimedHalves {
+            halves,
+            half_duration,
+        } => {
+            if game.remaining.map_or(true, |remaining| remaining > 0.0) {
+                None
+            } else if game.period + 1 < halves {
+                info!("Half {} over", game.period + 1);
+                game.period += 1;
+                game.remaining = Some(half_duration);
+                Some(RuleOutcome::NextHalf)
+            } else if let Some(team) = leading_team(score) {
+                Some(RuleOutcome::End(MatchResult::Winner(team)))
+            } else if settings.sudden_death {
+                info!("Tied after regular time, sudden death");
+                game.sudden_death = true;
+                game.remaining = None;
+                Some(RuleOutcome::SuddenDeath)
+            } else {
+                Some(RuleOutcome::End(MatchResult::Draw))
+            }
+        }
+    }
+}
+
+/// Ends halves, sets and the match once the score or the clock demand it
+pub fn apply_match_rules(
+    mut commands: Commands,
+    settings: Res<MatchSettings>,
+    serve_settings: Res<ServeSettings>,
+    mut game: ResMut<Match>,
+    mut score: ResMut<Score>,
+    pucks: Query<Entity, (With<Puck>, Without<RemovedPuck>)>,
+    mut sounds: EventWriter<PlaySound>,
+) {
+    let outcome = match apply_rules(&mut game, &score, &settings) {
+        Some(outcome) => outcome,
+        None => return,
+    };
+    match outcome {
+        RuleOutcome::End(result) => end_match(&mut commands, &mut game, result, &mut sounds),
+        RuleOutcome::NextSet | RuleOutcome::NextHalf | RuleOutcome::SuddenDeath => {
+            if outcome == RuleOutcome::NextSet {
+                *score = Score::default();
+            }
+            for puck in &pucks {
+                start_serve(&mut commands, puck, None, &serve_settings);
+            }
+        }
+    }
+}
+
+/// Marks every entity spawned for the match over screen, despawned when leaving it
+#[derive(Component)]
+pub struct MatchOverUi;
+
+pub fn setup_match_over_screen(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    game: Res<Match>,
+    score: Res<Score>,
+    players: Query<(&Player, &Team)>,
+) {
+    let headline = match game.result {
+        Some(MatchResult::Winner(team)) => {
+            let names: Vec<_> = players
+                .iter()
+                .filter(|(_, player_team)| **player_team == team)
+                .map(|(player, _)| player.name.as_str())
+                .collect();
+            format!("{} wins!", names.join(" & "))
+        }
+        Some(MatchResult::Draw) | None => "Draw!".to_string(),
+    };
+    let details = if game.sets.is_empty() {
+        format!("{}:{}", score.goals(Team::Left), score.goals(Team::Right))
+    } else {
+        format!("Sets {}:{}", game.sets(Team::Left), game.sets(Team::Right))
+    };
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                format!("{headline}\n{details}"),
+                TextStyle {
+                    font: fonts.arial.clone(),
+                    font_size: 160.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_alignment(TextAlignment::CENTER),
+            transform: Transform::from_xyz(0.0, 0.0, 20.0),
+            ..default()
+        })
+        .insert(MatchOverUi);
+}
+
+pub fn leave_match_over_screen(
+    mut commands: Commands,
+    time: Res<Time>,
+    keyboard_input: Res<Input<KeyCode>>,
+    settings: Res<MatchSettings>,
+    mut shown_for: Local<f32>,
+) {
+    *shown_for += time.delta_seconds();
+    if *shown_for >= settings.result_duration || keyboard_input.just_pressed(KeyCode::Return) {
+        *shown_for = 0.0;
+        commands.insert_resource(NextState(AppState::Lobby));
+    }
+}
+
+pub fn cleanup_match_over_screen(
+    mut commands: Commands,
+    entities: Query<Entity, With<MatchOverUi>>,
+) {
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[derive(SystemLabel, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MatchSystem {
+    ApplyRules,
+}
+
+/// Marks every entity spawned for the lobby, despawned when leaving it
+#[derive(Component)]
+pub struct LobbyUi;
+
+pub fn setup_lobby(mut commands: Commands, fonts: Res<Fonts>, settings: Res<MatchSettings>) {
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                format!(
+                    "{}\nPress Enter to start",
+                    Match::new(&settings).period_label(&settings)
+                ),
+                TextStyle {
+                    font: fonts.arial.clone(),
+                    font_size: 120.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_alignment(TextAlignment::CENTER),
+            transform: Transform::from_xyz(0.0, 0.0, 20.0),
+            ..default()
+        })
+        .insert(LobbyUi);
+}
+
+pub fn start_match_from_lobby(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    settings: Res<MatchSettings>,
+    serve_settings: Res<ServeSettings>,
+    pucks: Query<Entity, (With<Puck>, Without<RemovedPuck>)>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        restart_match(&mut commands, &settings, &serve_settings, &pucks);
+        commands.insert_resource(NextState(AppState::Game(GameState::Running)));
+    }
+}
+
+pub fn cleanup_lobby(mut commands: Commands, entities: Query<Entity, With<LobbyUi>>) {
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(format: MatchFormat, sudden_death: bool) -> MatchSettings {
+        MatchSettings {
+            format,
+            sudden_death,
+            ..default()
+        }
+    }
+
+    fn score(left: usize, right: usize) -> Score {
+        let mut score = Score::default();
+        for _ in 0..left {
+            score.add_goal(Team::Left);
+        }
+        for _ in 0..right {
+            score.add_goal(Team::Right);
+        }
+        score
+    }
+
+    #[test]
+    fn first_to_ends_at_the_goal_limit() {
+        let settings = settings(MatchFormat::FirstTo { goals: 3 }, true);
+        let mut game = Match::new(&settings);
+        assert_eq!(apply_rules(&mut game, &score(2, 2), &settings), None);
+        assert_eq!(
+            apply_rules(&mut game, &score(2, 3), &settings),
+            Some(RuleOutcome::End(MatchResult::Winner(Team::Right)))
+        );
+    }
+
+    #[test]
+    fn best_of_sets_needs_more_than_half_of_the_sets() {
+        let settings = settings(
+            MatchFormat::BestOfSets {
+                sets: 4,
+                goals_per_set: 2,
+            },
+            true,
+        );
+        let mut game = Match::new(&settings);
+        assert_eq!(apply_rules(&mut game, &score(1, 1), &settings), None);
+        for period in 1..=2 {
+            assert_eq!(
+                apply_rules(&mut game, &score(2, 0), &settings),
+                Some(RuleOutcome::NextSet)
+            );
+            assert_eq!(game.period, period);
+        }
+        assert_eq!(game.sets(Team::Left), 2);
+        assert_eq!(
+            apply_rules(&mut game, &score(1, 2), &settings),
+            Some(RuleOutcome::NextSet)
+        );
+        assert_eq!(
+            apply_rules(&mut game, &score(2, 1), &settings),
+            Some(RuleOutcome::End(MatchResult::Winner(Team::Left)))
+        );
+        assert_eq!(game.sets(Team::Left), 3);
+        assert_eq!(game.sets(Team::Right), 1);
+    }
+
+    #[test]
+    fn timed_halves_end_with_the_clock() {
+        let format = MatchFormat::TimedHalves {
+            halves: 2,
+            half_duration: 60.0,
+        };
+        let settings = settings(format, true);
+        let mut game = Match::new(&settings);
+        game.remaining = Some(0.5);
+        assert_eq!(apply_rules(&mut game, &score(0, 1), &settings), None);
+
+        game.remaining = Some(0.0);
+        assert_eq!(
+            apply_rules(&mut game, &score(0, 1), &settings),
+            Some(RuleOutcome::NextHalf)
+        );
+        assert_eq!(game.period, 1);
+        assert_eq!(game.remaining, Some(60.0));
+
+        game.remaining = Some(0.0);
+        assert_eq!(
+            apply_rules(&mut game, &score(2, 1), &settings),
+            Some(RuleOutcome::End(MatchResult::Winner(Team::Left)))
+        );
+    }
+
+    #[test]
+    fn tie_after_regular_time_goes_to_sudden_death() {
+        let format = MatchFormat::TimedHalves {
+            halves: 1,
+            half_duration: 60.0,
+        };
+        let settings = settings(format, true);
+        let mut game = Match::new(&settings);
+        game.remaining = Some(0.0);
+        assert_eq!(
+            apply_rules(&mut game, &score(1, 1), &settings),
+            Some(RuleOutcome::SuddenDeath)
+        );
+        assert!(game.sudden_death);
+        assert_eq!(game.remaining, None);
+
+        assert_eq!(apply_rules(&mut game, &score(1, 1), &settings), None);
+        assert_eq!(
+            apply_rules(&mut game, &score(1, 2), &settings),
+            Some(RuleOutcome::End(MatchResult::Winner(Team::Right)))
+        );
+    }
+
+    #[test]
+    fn tie_without_sudden_death_is_a_draw() {
+        let format = MatchFormat::TimedHalves {
+            halves: 1,
+            half_duration: 60.0,
+        };
+        let settings = settings(format, false);
+        let mut game = Match::new(&settings);
+        game.remaining = Some(0.0);
+        assert_eq!(
+            apply_rules(&mut game, &score(2, 2), &settings),
+            Some(RuleOutcome::End(MatchResult::Draw))
+        );
+    }
+
+    #[test]
+    fn decided_match_stays_decided() {
+        let settings = settings(MatchFormat::FirstTo { goals: 1 }, true);
+        let mut game = Match::new(&settings);
+        game.result = Some(MatchResult::Draw);
+        assert_eq!(apply_rules(&mut game, &score(3, 0), &settings), None);
+    }
+}
@@ -1,29 +1,101 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 
-use crate::{puck::Puck, TABLE_LENGTH};
+use std::collections::HashMap;
+
+use crate::{
+    player::Team,
+    puck::{MultiPuckSettings, Puck, RemovedPuck, TemporaryPuck},
+    serve::{start_serve, ServeSettings},
+    simulation::SimulationClock,
+    table::Goal,
+};
+
+pub struct GoalScored {
+    /// Team that scored, i.e. the opponent of the team defending the goal
+    pub team: Team,
+    pub puck: Entity,
+    // Not read by any system yet, kept for consumers of the event
+    #[allow(dead_code)]
+    pub puck_speed: f32,
+    #[allow(dead_code)]
+    pub time: f64,
+}
 
 #[derive(Default, Debug)]
 pub struct Score {
-    pub right: usize,
-    pub left: usize,
+    goals: HashMap<Team, usize>,
+}
+
+impl Score {
+    pub fn goals(&self, team: Team) -> usize {
+        self.goals.get(&team).copied().unwrap_or_default()
+    }
+
+    pub fn add_goal(&mut self, team: Team) {
+        *self.goals.entry(team).or_default() += 1;
+    }
 }
 
 pub fn detect_goals(
-    mut score: ResMut<Score>,
-    mut pucks: Query<(&mut Transform, &mut Velocity), With<Puck>>,
+    clock: Res<SimulationClock>,
+    mut collision_events: EventReader<CollisionEvent>,
+    goals: Query<&Goal>,
+    pucks: Query<&Velocity, With<Puck>>,
+    mut goal_events: EventWriter<GoalScored>,
 ) {
-    for (mut transform, mut velocity) in &mut pucks {
-        if transform.translation.x.abs() > TABLE_LENGTH / 2.0 {
-            if transform.translation.x.is_sign_negative() {
-                score.right += 1;
+    for collision_event in collision_events.iter() {
+        if let CollisionEvent::Started(first, second, _) = *collision_event {
+            let (goal, puck) = if goals.contains(first) {
+                (first, second)
             } else {
-                score.left += 1;
+                (second, first)
+            };
+            if let (Ok(goal), Ok(velocity)) = (goals.get(goal), pucks.get(puck)) {
+                let team = goal.team.opponent();
+                let puck_speed = velocity.linvel.length();
+                info!("Goal for {:?} at {:.0} px/s", team, puck_speed);
+                goal_events.send(GoalScored {
+                    team,
+                    puck,
+                    puck_speed,
+                    time: clock.seconds_since_startup(),
+                });
             }
+        }
+    }
+}
 
-            transform.translation.x = 0.0;
-            transform.translation.y = 0.0;
-            *velocity = Velocity::zero();
+pub fn update_score(
+    mut commands: Commands,
+    mut goal_events: EventReader<GoalScored>,
+    mut score: ResMut<Score>,
+    serve_settings: Res<ServeSettings>,
+    multi_puck: Res<MultiPuckSettings>,
+    pucks: Query<Option<&TemporaryPuck>, (With<Puck>, Without<RemovedPuck>)>,
+) {
+    let mut puck_count = pucks.iter().count();
+    for goal in goal_events.iter() {
+        let temporary = match pucks.get(goal.puck) {
+            Ok(temporary) => temporary.is_some(),
+            Err(_) => continue,
+        };
+        score.add_goal(goal.team);
+        if (multi_puck.enabled || temporary) && puck_count > 1 {
+            // The other pucks stay in play
+            commands
+                .entity(goal.puck)
+                .insert(RemovedPuck)
+                .insert(Sensor);
+            puck_count -= 1;
+        } else {
+            // The conceding player serves
+            start_serve(
+                &mut commands,
+                goal.puck,
+                Some(goal.team.opponent()),
+                &serve_settings,
+            );
         }
     }
 }
@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    audio::{PlaySound, SoundEffect},
+    player::Team,
+    puck::Puck,
+    simulation::SimulationClock,
+    stick::Stick,
+    table::TableGeometry,
+};
+
+/// Additional gap in pixels kept between a served puck and the closest stick or puck
+const CLEARANCE_MARGIN: f32 = 10.0;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ServeSettings {
+    /// Seconds the puck is held in place before it is released
+    pub countdown: f32,
+    /// Distance in pixels from the center line at which the conceding player receives the puck
+    pub distance: f32,
+}
+
+impl Default for ServeSettings {
+    fn default() -> Self {
+        Self {
+            countdown: 3.0,
+            distance: 480.0,
+        }
+    }
+}
+
+/// A puck that is held in place until its countdown ran out, it is a sensor in the meantime so
+/// that sticks cannot hit it
+#[derive(Component)]
+pub struct Serve {
+    /// Team receiving the puck, `None` for a faceoff at the center
+    pub team: Option<Team>,
+    pub remaining: f32,
+    position: Option<Vec2>,
+    /// Last whole second of the countdown a sound was played for
+    announced: u32,
+}
+
+pub fn start_serve(
+    commands: &mut Commands,
+    puck: Entity,
+    team: Option<Team>,
+    settings: &ServeSettings,
+) {
+    commands
+        .entity(puck)
+        .insert(Serve {
+            team,
+            remaining: settings.countdown,
+            position: None,
+            announced: u32::MAX,
+        })
+        .insert(Sensor)
+        .insert(Velocity::zero());
+}
+
+/// Something a served puck must keep away from, its center and the smallest distance between
+/// it and the puck's center at which they do not touch
+type Obstacle = (Vec2, f32);
+
+fn is_clear(position: Vec2, obstacles: &[Obstacle]) -> bool {
+    obstacles
+        .iter()
+        .all(|&(center, clearance)| center.distance(position) >= clearance)
+}
+
+/// Serve spot on the receiving team's side, moved along the side's width if it is covered
+fn serve_position(
+    team: Option<Team>,
+    settings: &ServeSettings,
+    geometry: &TableGeometry,
+    obstacles: &[Obstacle],
+) -> Vec2 {
+    let base = Vec2::new(
+        team.map_or(0.0, |team| team.side() * settings.distance),
+        0.0,
+    );
+    if is_clear(base, obstacles) {
+        return base;
+    }
+
+    let step_size = geometry.puck_diameter + CLEARANCE_MARGIN;
+    let max_offset = geometry.width / 2.0 - geometry.puck_diameter / 2.0;
+    let mut step = 1.0;
+    while step * step_size <= max_offset {
+        for sign in [1.0, -1.0] {
+            let candidate = base + Vec2::new(0.0, sign * step * step_size);
+            if is_clear(candidate, obstacles) {
+                return candidate;
+            }
+        }
+        step += 1.0;
+    }
+    base
+}
+
+pub fn run_serves(
+    mut commands: Commands,
+    clock: Res<SimulationClock>,
+    settings: Res<ServeSettings>,
+    geometry: Res<TableGeometry>,
+    mut serves: Query<(Entity, &mut Serve, &mut Transform, &mut Velocity), With<Puck>>,
+    free_pucks: Query<&Transform, (With<Puck>, Without<Serve>)>,
+    sticks: Query<&Transform, (With<Stick>, Without<Sensor>, Without<Puck>)>,
+    mut sounds: EventWriter<PlaySound>,
+) {
+    let stick_clearance =
+        (geometry.stick_diameter + geometry.puck_diameter) / 2.0 + CLEARANCE_MARGIN;
+    let puck_clearance = geometry.puck_diameter + CLEARANCE_MARGIN;
+    let sticks: Vec<_> = sticks
+        .iter()
+        .map(|transform| (transform.translation.truncate(), stick_clearance))
+        .collect();
+    // Held pucks add their spots as they are placed, so that two serves never share a spot
+    let mut pucks: Vec<_> = free_pucks
+        .iter()
+        .map(|transform| (transform.translation.truncate(), puck_clearance))
+        .collect();
+
+    for (puck, mut serve, mut transform, mut velocity) in &mut serves {
+        let obstacles = [sticks.as_slice(), pucks.as_slice()].concat();
+        let position = match serve.position {
+            Some(position) if is_clear(position, &obstacles) => position,
+            _ => serve_position(serve.team, &settings, &geometry, &obstacles),
+        };
+        pucks.push((position, puck_clearance));
+        serve.position = Some(position);
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+        *velocity = Velocity::zero();
+
+        serve.remaining -= clock.delta_seconds();
+        let second = serve.remaining.max(0.0).ceil() as u32;
+        if second > 0 && second < serve.announced {
+            serve.announced = second;
+            sounds.send(PlaySound(SoundEffect::Countdown));
+        }
+
+        // Releasing the puck inside a stick would launch it, so it is held until the spot is clear
+        if serve.remaining <= 0.0 && is_clear(position, &obstacles) {
+            commands.entity(puck).remove::<Serve>().remove::<Sensor>();
+        }
+    }
+}
@@ -0,0 +1,419 @@
+use std::time::Duration;
+
+use bevy::{ecs::schedule::ShouldRun, prelude::*};
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+
+use crate::app_state::{AppState, GameState};
+
+/// Label of the fixed timestep running gameplay, stick updates and physics
+pub const SIMULATION_STEP: &str = "simulation_step";
+
+/// Child stages of a simulation step, run in this order so that events are always read in the
+/// step that sent them
+#[derive(Clone, Copy, Debug)]
+pub enum SimulationStage {
+    /// Lidar scans, tracks and stick positions
+    Input,
+    /// Serves, timers and spawning before physics
+    Gameplay,
+    SyncBackend,
+    StepSimulation,
+    Writeback,
+    /// Reacting to the collisions of this step
+    Collisions,
+    /// Score, sounds and recording the resulting positions
+    Score,
+    /// Match rules and advancing the clock
+    Rules,
+}
+
+impl SimulationStage {
+    const COUNT: usize = 8;
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SimulationSettings {
+    /// Simulation steps per second
+    pub rate: f64,
+    /// Physics substeps per simulation step
+    pub substeps: usize,
+    /// Seed of the random numbers drawn by the simulation
+    pub seed: u64,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            rate: 60.0,
+            substeps: 1,
+            seed: 0,
+        }
+    }
+}
+
+impl SimulationSettings {
+    pub fn step(&self) -> f32 {
+        (1.0 / self.rate) as f32
+    }
+
+    pub fn timestep_mode(&self) -> TimestepMode {
+        TimestepMode::Fixed {
+            dt: self.step(),
+            substeps: self.substeps,
+        }
+    }
+}
+
+/// Time of the simulation, only advanced by simulation steps while tracking, so that the same
+/// recorded scans always land in the same steps
+pub struct SimulationClock {
+    steps: u64,
+    step: f32,
+}
+
+impl SimulationClock {
+    pub fn new(settings: &SimulationSettings) -> Self {
+        Self {
+            steps: 0,
+            step: settings.step(),
+        }
+    }
+
+    /// Index of the current step
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.step
+    }
+
+    pub fn seconds_since_startup(&self) -> f64 {
+        self.steps as f64 * self.step as f64
+    }
+}
+
+/// Random numbers of the simulation, only drawn inside simulation steps so that playing back the
+/// same recorded scans also gives the same computer moves and power-ups
+pub struct SimulationRng(pub StdRng);
+
+/// Whether scans are tracked, the simulation clock and physics only advance in these states
+pub fn is_tracking(app_state: Res<CurrentState<AppState>>) -> bool {
+    matches!(
+        app_state.0,
+        AppState::Game(GameState::Running) | AppState::Tracker
+    )
+}
+
+fn run_while_tracking(app_state: Res<CurrentState<AppState>>) -> ShouldRun {
+    if is_tracking(app_state) {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+pub fn advance_simulation_clock(mut clock: ResMut<SimulationClock>) {
+    clock.steps += 1;
+}
+
+/// Sets up the fixed timestep and runs rapier inside it, requires the rapier plugin to be added
+/// without its default system setup
+pub struct SimulationPlugin(pub SimulationSettings);
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SimulationClock::new(&self.0))
+            .insert_resource(SimulationRng(StdRng::seed_from_u64(self.0.seed)))
+            .insert_resource(self.0.clone())
+            .add_fixed_timestep(Duration::from_secs_f64(1.0 / self.0.rate), SIMULATION_STEP);
+        for _ in 1..SimulationStage::COUNT {
+            app.add_fixed_timestep_child_stage(SIMULATION_STEP);
+        }
+        for (stage, physics_stage) in [
+            (SimulationStage::SyncBackend, PhysicsStages::SyncBackend),
+            (
+                SimulationStage::StepSimulation,
+                PhysicsStages::StepSimulation,
+            ),
+            (SimulationStage::Writeback, PhysicsStages::Writeback),
+        ] {
+            app.add_fixed_timestep_system_set(
+                SIMULATION_STEP,
+                stage.index(),
+                // Physics pauses with the clock, so that waiting in the lobby does not move
+                // anything and the same scans always meet the same table
+                RapierPhysicsPlugin::<NoUserData>::get_systems(physics_stage)
+                    .with_run_criteria(run_while_tracking),
+            );
+        }
+        // Despawns are detected once per frame, after every system that could despawn
+        app.add_system_set_to_stage(
+            CoreStage::Last,
+            RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::DetectDespawn),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        time::{Duration, Instant},
+    };
+
+    use bevy::transform::TransformPlugin;
+    use bevy_prototype_debug_lines::DebugLines;
+
+    use super::*;
+    use crate::{
+        ai::{drive_computer_players, Difficulty},
+        assets::Textures,
+        classification::ClassificationSettings,
+        config::Arguments,
+        lidar_communication::{
+            handle_lidar_data, setup_lidar_communication, LidarScan, LidarSettings, LidarSystem,
+        },
+        physics::{limit_puck_speed, PuckPhysics},
+        player::{PlayerSettings, Roster, Team},
+        puck::{spawn_puck, Puck},
+        stick::{follow_tracks, setup_stick, update_stick_presence, Stick, StickSettings},
+        table::TableGeometry,
+        tracking::{update_tracks, TrackingSettings, Tracks},
+        zone::ZoneSettings,
+    };
+
+    const STEPS: u64 = 180;
+    const RAY_COUNT: usize = 361;
+    /// Millimeters of the recorded rays per pixel
+    const PIXELS_PER_MILLIMETER: f32 = 0.5;
+
+    /// Rays of a lidar at the lower rail seeing only a stick of the given position
+    fn scan_of_stick(position: Vec2, geometry: &TableGeometry) -> Vec<usize> {
+        let relative = position - geometry.lidar_origin();
+        let center = Vec2::new(relative.y, -relative.x) / PIXELS_PER_MILLIMETER;
+        let radius = geometry.stick_diameter / 2.0 / PIXELS_PER_MILLIMETER;
+        (0..RAY_COUNT)
+            .map(|index| {
+                let direction = Vec2::from_angle((-138.0 + 0.75 * index as f32).to_radians());
+                let along = direction.dot(center);
+                let discriminant = along * along - center.length_squared() + radius * radius;
+                if along > 0.0 && discriminant > 0.0 {
+                    (along - discriminant.sqrt()).round() as usize
+                } else {
+                    // Far outside the table, zero would shift the following rays
+                    100_000
+                }
+            })
+            .collect()
+    }
+
+    fn write_recording(path: &str) {
+        let geometry = TableGeometry::default();
+        let lines: Vec<_> = (0..STEPS)
+            .map(|step| {
+                let position = Vec2::new(-700.0 + 3.0 * step as f32, 150.0);
+                let rays: Vec<_> = scan_of_stick(position, &geometry)
+                    .iter()
+                    .map(usize::to_string)
+                    .collect();
+                format!("{} {}", step, rays.join(" "))
+            })
+            .collect();
+        fs::write(path, lines.join("\n")).unwrap();
+    }
+
+    fn spawn_moving_puck(
+        mut commands: Commands,
+        textures: Res<Textures>,
+        geometry: Res<TableGeometry>,
+        physics: Res<PuckPhysics>,
+    ) {
+        let puck = spawn_puck(&mut commands, &textures, &geometry, &physics, Vec2::ZERO);
+        commands
+            .entity(puck)
+            .insert(Velocity::linear(Vec2::new(900.0, 400.0)));
+    }
+
+    /// Waits in the lobby for `lobby_frames`, then plays the recording through scans, tracks,
+    /// sticks, the computer player and physics and returns the final positions of every puck and
+    /// stick
+    fn play_recording(path: &str, lobby_frames: u32) -> Vec<(Entity, Vec3)> {
+        let settings = SimulationSettings::default();
+        let players = vec![
+            PlayerSettings {
+                name: "Left".to_string(),
+                team: Team::Left,
+                zone: None,
+                start: None,
+                computer: None,
+            },
+            PlayerSettings {
+                name: "Right".to_string(),
+                team: Team::Right,
+                zone: None,
+                start: None,
+                computer: Some(Difficulty::Hard),
+            },
+        ];
+
+        let mut app = App::new();
+        app.add_plugin(TransformPlugin)
+            .add_plugin(
+                RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0)
+                    .with_default_system_setup(false),
+            )
+            .add_plugin(SimulationPlugin(settings.clone()))
+            .insert_resource(RapierConfiguration {
+                gravity: Vec2::ZERO,
+                timestep_mode: settings.timestep_mode(),
+                ..default()
+            })
+            .insert_resource(Time::default())
+            .add_loopless_state(AppState::Lobby)
+            .insert_resource(Arguments {
+                config_path: String::new(),
+                tracker: false,
+                record_scans: None,
+                play_scans: Some(path.to_string()),
+            })
+            .insert_resource(Textures {
+                table: Handle::default(),
+                center_circle: Handle::default(),
+                goal_post: Handle::default(),
+                puck: Handle::default(),
+                stick: Handle::default(),
+            })
+            .insert_resource(TableGeometry::default())
+            .insert_resource(ClassificationSettings::default())
+            .insert_resource(TrackingSettings::default())
+            .insert_resource(StickSettings::default())
+            .insert_resource(ZoneSettings::default())
+            .insert_resource(PuckPhysics::default())
+            .insert_resource(Roster(players))
+            .init_resource::<Tracks>()
+            .init_resource::<DebugLines>()
+            .add_event::<LidarScan>()
+            .add_startup_system(setup_lidar_communication)
+            .add_startup_system(setup_stick)
+            // In a later stage than the sticks, so that both runs give them the same entities
+            .add_startup_system_to_stage(StartupStage::PostStartup, spawn_moving_puck)
+            .add_startup_system_to_stage(
+                StartupStage::PostStartup,
+                |mut lidar_settings: ResMut<LidarSettings>| {
+                    lidar_settings.pixels_per_meter = PIXELS_PER_MILLIMETER;
+                },
+            )
+            // Scheduled as in `main`
+            .add_fixed_timestep_system(
+                SIMULATION_STEP,
+                SimulationStage::Input.index(),
+                handle_lidar_data
+                    .run_if(is_tracking)
+                    .label(LidarSystem::ReceiveScan),
+            )
+            .add_fixed_timestep_system(
+                SIMULATION_STEP,
+                SimulationStage::Input.index(),
+                update_tracks
+                    .run_if(is_tracking)
+                    .label(LidarSystem::UpdateTracks)
+                    .after(LidarSystem::ReceiveScan),
+            )
+            .add_fixed_timestep_system(
+                SIMULATION_STEP,
+                SimulationStage::Input.index(),
+                follow_tracks
+                    .run_in_state(AppState::Game(GameState::Running))
+                    .label(LidarSystem::FollowTracks)
+                    .after(LidarSystem::UpdateTracks),
+            )
+            .add_fixed_timestep_system(
+                SIMULATION_STEP,
+                SimulationStage::Input.index(),
+                drive_computer_players
+                    .run_in_state(AppState::Game(GameState::Running))
+                    .label(LidarSystem::DriveComputers)
+                    .after(LidarSystem::FollowTracks),
+            )
+            .add_fixed_timestep_system(
+                SIMULATION_STEP,
+                SimulationStage::Input.index(),
+                update_stick_presence
+                    .run_in_state(AppState::Game(GameState::Running))
+                    .after(LidarSystem::DriveComputers),
+            )
+            .add_fixed_timestep_system(
+                SIMULATION_STEP,
+                SimulationStage::Gameplay.index(),
+                limit_puck_speed.run_in_state(AppState::Game(GameState::Running)),
+            )
+            .add_fixed_timestep_system(
+                SIMULATION_STEP,
+                SimulationStage::Rules.index(),
+                advance_simulation_clock.run_if(is_tracking),
+            );
+
+        // Exactly one simulation step per update after the first, independent of how long an
+        // update takes
+        let start = Instant::now();
+        app.world.resource_mut::<Time>().update_with_instant(start);
+        let step = Duration::from_secs_f64(1.0 / settings.rate);
+        for frame in 0..=lobby_frames + STEPS as u32 {
+            // The state changes after the simulation step of an update, so the game is entered one
+            // update before its first step
+            if frame == lobby_frames {
+                app.world
+                    .insert_resource(NextState(AppState::Game(GameState::Running)));
+            }
+            app.world
+                .resource_mut::<Time>()
+                .update_with_instant(start + step * frame);
+            app.update();
+        }
+        assert_eq!(app.world.resource::<SimulationClock>().steps(), STEPS);
+
+        let mut query = app
+            .world
+            .query_filtered::<(Entity, &Transform), Or<(With<Puck>, With<Stick>)>>();
+        let mut positions: Vec<_> = query
+            .iter(&app.world)
+            .map(|(entity, transform)| (entity, transform.translation))
+            .collect();
+        positions.sort_by_key(|&(entity, _)| entity);
+        positions
+    }
+
+    #[test]
+    fn same_recording_gives_same_positions() {
+        let path = std::env::temp_dir().join("airhockey_determinism.scans");
+        let path = path.to_str().unwrap();
+        write_recording(path);
+
+        let first = play_recording(path, 0);
+        let second = play_recording(path, 0);
+        assert_eq!(first.len(), 3);
+        assert_eq!(first, second);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn waiting_in_the_lobby_does_not_change_the_match() {
+        let path = std::env::temp_dir().join("airhockey_lobby.scans");
+        let path = path.to_str().unwrap();
+        write_recording(path);
+
+        let direct = play_recording(path, 0);
+        let after_lobby = play_recording(path, 45);
+        assert_eq!(direct, after_lobby);
+        fs::remove_file(path).unwrap();
+    }
+}
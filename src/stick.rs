@@ -1,44 +1,237 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
 
-use crate::{assets::Textures, STICK_DIAMETER};
+use crate::{
+    ai::ComputerPlayer,
+    assets::Textures,
+    physics::{surface_restitution, PuckPhysics},
+    player::{Player, Roster, Team},
+    puck::Puck,
+    simulation::SimulationClock,
+    table::TableGeometry,
+    tracking::{TrackId, Tracks},
+    zone::{restrict_to_half, PlayerZone, ZoneSettings},
+};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct StickSettings {
+    /// Seconds without a track until a stick is considered lost
+    pub lost_timeout: f64,
+    /// Seconds to fade a stick out when lost and back in when re-acquired
+    pub fade_duration: f32,
+}
+
+impl Default for StickSettings {
+    fn default() -> Self {
+        Self {
+            lost_timeout: 0.5,
+            fade_duration: 0.3,
+        }
+    }
+}
 
 #[derive(Component)]
-pub struct LeftStick;
+pub struct Stick;
+
+/// Track a stick is bound to, kept while the track is coasted through occlusions
+#[derive(Component, Default)]
+pub struct StickTrack(pub Option<TrackId>);
 
+/// Whether a stick is currently tracked, lost sticks are faded out and do not collide
 #[derive(Component)]
-pub struct RightStick;
-
-pub fn setup_stick(mut commands: Commands, textures: Res<Textures>) {
-    commands
-        .spawn()
-        .insert(LeftStick)
-        .insert(RigidBody::KinematicPositionBased)
-        .insert(Collider::ball(STICK_DIAMETER / 2.0))
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite {
-                custom_size: Some(Vec2::new(STICK_DIAMETER, STICK_DIAMETER)),
-                ..default()
-            },
-            texture: textures.stick.clone(),
-            ..default()
-        })
-        .insert_bundle(TransformBundle::from(Transform::from_xyz(
-            -1000.0, 0.0, 2.0,
-        )));
-
-    commands
-        .spawn()
-        .insert(RightStick)
-        .insert(RigidBody::KinematicPositionBased)
-        .insert(Collider::ball(STICK_DIAMETER / 2.0))
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite {
-                custom_size: Some(Vec2::new(STICK_DIAMETER, STICK_DIAMETER)),
+pub struct StickPresence {
+    pub lost: bool,
+    pub last_tracked: f64,
+    pub opacity: f32,
+}
+
+impl Default for StickPresence {
+    fn default() -> Self {
+        Self {
+            lost: true,
+            last_tracked: f64::NEG_INFINITY,
+            opacity: 0.0,
+        }
+    }
+}
+
+pub fn setup_stick(
+    mut commands: Commands,
+    textures: Res<Textures>,
+    geometry: Res<TableGeometry>,
+    physics: Res<PuckPhysics>,
+    roster: Res<Roster>,
+) {
+    for (id, player) in roster.0.iter().enumerate() {
+        info!("Spawning stick of {} ({:?})", player.name, player.team);
+        let zone = match &player.zone {
+            Some(zone) => PlayerZone::new(zone),
+            None => PlayerZone::half(player.team, &geometry),
+        };
+        let start = player
+            .start
+            .map_or_else(|| geometry.goal_center(player.team), Vec2::from);
+        let mut stick = commands.spawn();
+        stick
+            .insert(Stick)
+            .insert(Player {
+                id,
+                name: player.name.clone(),
+            })
+            .insert(player.team)
+            .insert(zone)
+            .insert(StickTrack::default())
+            .insert(StickPresence::default())
+            .insert(RigidBody::KinematicPositionBased)
+            .insert(Collider::ball(geometry.stick_diameter / 2.0))
+            .insert(surface_restitution(physics.stick_restitution))
+            .insert(Sensor)
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite {
+                    custom_size: Some(Vec2::new(geometry.stick_diameter, geometry.stick_diameter)),
+                    ..default()
+                },
+                texture: textures.stick.clone(),
                 ..default()
-            },
-            texture: textures.stick.clone(),
-            ..default()
-        })
-        .insert_bundle(TransformBundle::from(Transform::from_xyz(1000.0, 0.0, 2.0)));
+            })
+            .insert_bundle(TransformBundle::from(Transform::from_translation(
+                start.extend(2.0),
+            )));
+        if let Some(difficulty) = player.computer {
+            info!(
+                "{} is played by the computer ({:?})",
+                player.name, difficulty
+            );
+            stick.insert(ComputerPlayer::new(difficulty));
+        }
+    }
+}
+
+pub fn follow_tracks(
+    tracks: Res<Tracks>,
+    geometry: Res<TableGeometry>,
+    zone_settings: Res<ZoneSettings>,
+    mut sticks: Query<
+        (
+            &mut Transform,
+            &mut StickTrack,
+            &StickPresence,
+            &PlayerZone,
+            &Team,
+        ),
+        Without<ComputerPlayer>,
+    >,
+) {
+    let mut claimed: Vec<_> = sticks
+        .iter()
+        .filter_map(|(_, stick_track, _, _, _)| stick_track.0)
+        .filter(|&id| tracks.get(id).is_some())
+        .collect();
+
+    let low_pass = 0.95;
+    for (mut transform, mut stick_track, presence, zone, &team) in &mut sticks {
+        let bound_track = stick_track.0.and_then(|id| tracks.get(id));
+        let track = bound_track.or_else(|| {
+            tracks
+                .tracks
+                .iter()
+                .filter(|track| !track.occluded && !claimed.contains(&track.id))
+                .filter(|track| zone.contains(track.position))
+                .min_by(|left, right| {
+                    let left_distance = (left.position - transform.translation.truncate()).length();
+                    let right_distance =
+                        (right.position - transform.translation.truncate()).length();
+                    left_distance.total_cmp(&right_distance)
+                })
+        });
+        stick_track.0 = track.map(|track| track.id);
+
+        if let Some(track) = track {
+            // Two sticks picking up a track in the same step must not pick the same one
+            if !claimed.contains(&track.id) {
+                claimed.push(track.id);
+            }
+            // A re-acquired stick jumps to the player instead of gliding across the table
+            let low_pass = if presence.lost { 1.0 } else { low_pass };
+            let mut position =
+                (1.0 - low_pass) * transform.translation.truncate() + low_pass * track.position;
+            // A stick reaching into a rail would shove the puck through it, a big stick included
+            let radius = geometry.stick_diameter / 2.0 * transform.scale.x;
+            position = geometry.clamp_inside(position, radius);
+            if zone_settings.restrict_to_half {
+                position = restrict_to_half(position, team, &geometry);
+            }
+            transform.translation.x = position.x;
+            transform.translation.y = position.y;
+        }
+    }
+}
+
+pub fn update_stick_presence(
+    mut commands: Commands,
+    clock: Res<SimulationClock>,
+    settings: Res<StickSettings>,
+    geometry: Res<TableGeometry>,
+    tracks: Res<Tracks>,
+    pucks: Query<&Transform, With<Puck>>,
+    mut sticks: Query<
+        (
+            Entity,
+            &Player,
+            &Transform,
+            &StickTrack,
+            &mut StickPresence,
+            &mut Sprite,
+            Option<&Sensor>,
+            Option<&ComputerPlayer>,
+        ),
+        Without<Puck>,
+    >,
+) {
+    let now = clock.seconds_since_startup();
+    let fade_step = clock.delta_seconds() / settings.fade_duration;
+    for (entity, player, transform, stick_track, mut presence, mut sprite, sensor, computer) in
+        &mut sticks
+    {
+        let track = stick_track.0.and_then(|id| tracks.get(id));
+        if track.is_some() || computer.is_some() {
+            presence.last_tracked = now;
+        }
+        let lost = now - presence.last_tracked > settings.lost_timeout;
+        if lost != presence.lost {
+            if lost {
+                info!("Lost track of {} (player {})", player.name, player.id);
+            } else {
+                info!("Re-acquired {} (player {})", player.name, player.id);
+            }
+            presence.lost = lost;
+        }
+
+        presence.opacity = if lost {
+            (presence.opacity - fade_step).max(0.0)
+        } else {
+            (presence.opacity + fade_step).min(1.0)
+        };
+        let occluded = track.map_or(false, |track| track.occluded);
+        sprite
+            .color
+            .set_a(presence.opacity * if occluded { 0.5 } else { 1.0 });
+
+        if lost && sensor.is_none() {
+            commands.entity(entity).insert(Sensor);
+        } else if !lost && sensor.is_some() {
+            // Only re-enable the collider once the stick no longer overlaps a puck
+            let overlaps_puck = pucks.iter().any(|puck| {
+                (puck.translation - transform.translation)
+                    .truncate()
+                    .length()
+                    < (geometry.stick_diameter + geometry.puck_diameter) / 2.0
+            });
+            if !overlaps_puck {
+                commands.entity(entity).remove::<Sensor>();
+            }
+        }
+    }
 }
@@ -0,0 +1,190 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    assets::Textures,
+    player::Team,
+    puck::{Puck, RemovedPuck},
+    serve::{start_serve, Serve, ServeSettings},
+    simulation::SimulationClock,
+    stick::{Stick, StickPresence},
+    table::TableGeometry,
+    zone::PlayerZone,
+};
+
+/// Pixels between a resting puck and a rail within which it counts as lying against the rail
+const WALL_MARGIN: f32 = 10.0;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct StuckPuckSettings {
+    /// Move pucks that cannot be played anymore to the side of the closest player, pucks that
+    /// escaped the table are always served again
+    pub enabled: bool,
+    /// Seconds a puck has to be stuck before it is moved
+    pub timeout: f32,
+    /// Speed in pixels per second below which a puck counts as resting
+    pub min_speed: f32,
+    /// Seconds the spot a puck was stuck at stays marked
+    pub indicator_duration: f32,
+}
+
+impl Default for StuckPuckSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            timeout: 4.0,
+            min_speed: 20.0,
+            indicator_duration: 1.5,
+        }
+    }
+}
+
+/// Seconds a puck has been resting or wedged under a stick
+#[derive(Component, Default)]
+pub struct StuckTime(pub f32);
+
+/// Fading marker where a stuck puck was taken away
+#[derive(Component)]
+pub struct StuckIndicator {
+    remaining: f32,
+}
+
+pub fn recover_stuck_pucks(
+    mut commands: Commands,
+    clock: Res<SimulationClock>,
+    settings: Res<StuckPuckSettings>,
+    serve_settings: Res<ServeSettings>,
+    textures: Res<Textures>,
+    geometry: Res<TableGeometry>,
+    mut pucks: Query<
+        (Entity, &Transform, &Velocity, &mut StuckTime),
+        (With<Puck>, Without<Serve>, Without<RemovedPuck>),
+    >,
+    sticks: Query<(&Transform, &Team, &StickPresence, &PlayerZone), (With<Stick>, Without<Puck>)>,
+) {
+    if !settings.enabled {
+        return;
+    }
+    // A stick sitting on a puck keeps pushing it into the rail instead of hitting it
+    let wedge_distance = (geometry.stick_diameter + geometry.puck_diameter) / 2.0 * 0.9;
+    for (puck, transform, velocity, mut stuck_time) in &mut pucks {
+        let position = transform.translation.truncate();
+        // A puck resting in the open can still be picked up by a player who reaches it
+        let is_out_of_reach = geometry.distance_to_walls(position)
+            < geometry.puck_diameter / 2.0 + WALL_MARGIN
+            || sticks
+                .iter()
+                .filter(|(_, _, presence, _)| !presence.lost)
+                .all(|(_, _, _, zone)| !zone.contains(position));
+        let is_resting = velocity.linvel.length() < settings.min_speed && is_out_of_reach;
+        let is_wedged = sticks.iter().any(|(stick, _, presence, _)| {
+            !presence.lost && stick.translation.truncate().distance(position) < wedge_distance
+        });
+        if !is_resting && !is_wedged {
+            stuck_time.0 = 0.0;
+            continue;
+        }
+        stuck_time.0 += clock.delta_seconds();
+        if stuck_time.0 < settings.timeout {
+            continue;
+        }
+        stuck_time.0 = 0.0;
+
+        // The puck goes to the closest player, or to the half it rests on without players
+        let team = sticks
+            .iter()
+            .filter(|(_, _, presence, _)| !presence.lost)
+            .min_by(|(left, _, _, _), (right, _, _, _)| {
+                let distance = |stick: &Transform| stick.translation.truncate().distance(position);
+                distance(left).total_cmp(&distance(right))
+            })
+            .map_or_else(
+                || {
+                    if position.x < 0.0 {
+                        Team::Left
+                    } else {
+                        Team::Right
+                    }
+                },
+                |(_, &team, _, _)| team,
+            );
+        info!(
+            "Puck stuck at ({:.0}, {:.0}), moving it to {:?}",
+            position.x, position.y, team
+        );
+        start_serve(&mut commands, puck, Some(team), &serve_settings);
+        spawn_indicator(&mut commands, &textures, &geometry, &settings, position);
+    }
+}
+
+/// Serves a puck that left the table anywhere but through a goal mouth again, without a goal
+pub fn recover_escaped_pucks(
+    mut commands: Commands,
+    settings: Res<StuckPuckSettings>,
+    serve_settings: Res<ServeSettings>,
+    textures: Res<Textures>,
+    geometry: Res<TableGeometry>,
+    pucks: Query<(Entity, &Transform), (With<Puck>, Without<Serve>, Without<RemovedPuck>)>,
+) {
+    for (puck, transform) in &pucks {
+        let position = transform.translation.truncate();
+        if geometry.contains(position) || geometry.is_in_goal(position) {
+            continue;
+        }
+        warn!(
+            "Puck escaped the table at ({:.0}, {:.0}), serving it again",
+            position.x, position.y
+        );
+        start_serve(&mut commands, puck, None, &serve_settings);
+        spawn_indicator(
+            &mut commands,
+            &textures,
+            &geometry,
+            &settings,
+            geometry.clamp_inside(position, geometry.puck_diameter / 2.0),
+        );
+    }
+}
+
+fn spawn_indicator(
+    commands: &mut Commands,
+    textures: &Textures,
+    geometry: &TableGeometry,
+    settings: &StuckPuckSettings,
+    position: Vec2,
+) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: Color::RED,
+                custom_size: Some(Vec2::splat(geometry.puck_diameter * 1.5)),
+                ..default()
+            },
+            texture: textures.puck.clone(),
+            transform: Transform::from_translation(position.extend(1.5)),
+            ..default()
+        })
+        .insert(StuckIndicator {
+            remaining: settings.indicator_duration,
+        });
+}
+
+pub fn fade_stuck_indicators(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<StuckPuckSettings>,
+    mut indicators: Query<(Entity, &mut StuckIndicator, &mut Sprite)>,
+) {
+    for (entity, mut indicator, mut sprite) in &mut indicators {
+        indicator.remaining -= time.delta_seconds();
+        if indicator.remaining <= 0.0 {
+            commands.entity(entity).despawn_recursive();
+        } else {
+            sprite
+                .color
+                .set_a(indicator.remaining / settings.indicator_duration);
+        }
+    }
+}
@@ -1,23 +1,248 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
 
-use crate::{assets::Textures, GOAL_POST_DIAMETER, GOAL_WIDTH, TABLE_LENGTH, TABLE_WIDTH};
+use crate::{
+    assets::Textures,
+    physics::{surface_restitution, PuckPhysics},
+    player::Team,
+    zone::polygon_contains,
+};
 
-pub fn setup_table(mut commands: Commands, textures: Res<Textures>) {
+/// Depth of the goal sensor behind the goal mouth, deep enough that a fast puck cannot skip it
+const GOAL_SENSOR_DEPTH: f32 = 300.0;
+/// Thickness of the rails behind their inner surface, so that a puck shoved into a rail by a stick
+/// ends up inside the collider and is pushed back out instead of passing a thin line
+const WALL_THICKNESS: f32 = 100.0;
+
+/// Sensor behind the goal mouth of the team defending it
+#[derive(Component)]
+pub struct Goal {
+    pub team: Team,
+}
+
+/// One of the two posts of the goal defended by `team`
+#[derive(Component)]
+pub struct GoalPost {
+    pub team: Team,
+}
+
+#[derive(Component)]
+pub struct Wall;
+
+/// Marks every entity making up the table, despawned when switching arenas
+#[derive(Component)]
+pub struct TableEntity;
+
+/// Dimensions of the physical table in pixels, the origin is the center of the table
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TableGeometry {
+    pub width: f32,
+    pub length: f32,
+    pub goal_width: f32,
+    /// Vertical position of the center of both goal mouths
+    pub goal_offset: f32,
+    pub goal_post_diameter: f32,
+    pub puck_diameter: f32,
+    pub stick_diameter: f32,
+    /// Custom rails replacing the two default ones, set by arenas
+    pub rails: Option<Vec<Vec<[f32; 2]>>>,
+}
+
+impl Default for TableGeometry {
+    fn default() -> Self {
+        Self {
+            width: 1200.0,
+            length: 1920.0,
+            goal_width: 400.0,
+            goal_offset: 0.0,
+            goal_post_diameter: 40.0,
+            puck_diameter: 64.0,
+            stick_diameter: 120.0,
+            rails: None,
+        }
+    }
+}
+
+impl TableGeometry {
+    pub fn half_size(&self) -> Vec2 {
+        Vec2::new(self.length / 2.0, self.width / 2.0)
+    }
+
+    /// Center of the goal mouth defended by `team`
+    pub fn goal_center(&self, team: Team) -> Vec2 {
+        Vec2::new(team.side() * self.length / 2.0, self.goal_offset)
+    }
+
+    /// The rails, by default two each running from one goal mouth around a long side to the
+    /// other goal mouth
+    pub fn walls(&self) -> Vec<Vec<Vec2>> {
+        if let Some(rails) = &self.rails {
+            return rails
+                .iter()
+                .map(|rail| rail.iter().copied().map(Vec2::from).collect())
+                .collect();
+        }
+        [-1.0, 1.0]
+            .map(|side| {
+                let goal_y = self.goal_offset + side * self.goal_width / 2.0;
+                vec![
+                    Vec2::new(-self.length / 2.0, goal_y),
+                    Vec2::new(-self.length / 2.0, side * self.width / 2.0),
+                    Vec2::new(self.length / 2.0, side * self.width / 2.0),
+                    Vec2::new(self.length / 2.0, goal_y),
+                ]
+            })
+            .into()
+    }
+
+    fn wall_segments(&self) -> Vec<(Vec2, Vec2)> {
+        self.walls()
+            .iter()
+            .flat_map(|wall| wall.windows(2).map(|segment| (segment[0], segment[1])))
+            .collect()
+    }
+
+    /// Polygon enclosed by the two rails, closed by straight lines across the goal mouths
+    fn outline(&self) -> Vec<Vec2> {
+        let mut walls = self.walls().into_iter();
+        let mut outline = walls.next().unwrap_or_default();
+        for wall in walls {
+            outline.extend(wall.into_iter().rev());
+        }
+        outline
+    }
+
+    /// Distance from `point` to the closest rail
+    pub fn distance_to_walls(&self, point: Vec2) -> f32 {
+        self.wall_segments()
+            .into_iter()
+            .map(|(start, end)| closest_point_on_segment(point, start, end).distance(point))
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    pub fn goal_posts(&self) -> Vec<(Team, Vec2)> {
+        Team::ALL
+            .into_iter()
+            .flat_map(|team| {
+                [-1.0, 1.0].map(|post_side| {
+                    let offset =
+                        post_side * (self.goal_width / 2.0 + self.goal_post_diameter / 2.0);
+                    (team, self.goal_center(team) + Vec2::new(0.0, offset))
+                })
+            })
+            .collect()
+    }
+
+    /// The lidar is mounted at the center of the lower long edge
+    pub fn lidar_origin(&self) -> Vec2 {
+        Vec2::new(0.0, -self.width / 2.0)
+    }
+
+    /// Closest position to `position` at which a circle of `radius` lies within the table
+    pub fn clamp_inside(&self, position: Vec2, radius: f32) -> Vec2 {
+        let half_size = self.half_size() - radius;
+        let mut position = position.clamp(-half_size, half_size);
+        if self.rails.is_none() {
+            return position;
+        }
+        // Pushed off every rail segment it reaches into, a second pass settles it in corners
+        let segments = self.wall_segments();
+        for _ in 0..2 {
+            for &(start, end) in &segments {
+                let direction = end - start;
+                let fraction = (position - start).dot(direction) / direction.length_squared();
+                if !(0.0..=1.0).contains(&fraction) {
+                    continue;
+                }
+                let inward = -outward_normal(start, end);
+                let clearance = (position - start).dot(inward);
+                if clearance < radius {
+                    position += inward * (radius - clearance);
+                }
+            }
+        }
+        position
+    }
+
+    /// Whether a puck at `position` is behind the goal line of a goal mouth
+    pub fn is_in_goal(&self, position: Vec2) -> bool {
+        position.x.abs() >= self.length / 2.0
+            && (position.y - self.goal_offset).abs()
+                <= self.goal_width / 2.0 + self.puck_diameter / 2.0
+    }
+
+    /// Whether `point` lies within the rails, on the table side of the goal lines
+    pub fn contains(&self, point: Vec2) -> bool {
+        if self.rails.is_some() {
+            return polygon_contains(&self.outline(), point);
+        }
+        let half_size = self.half_size();
+        (-half_size.x..half_size.x).contains(&point.x)
+            && (-half_size.y..half_size.y).contains(&point.y)
+    }
+}
+
+/// Normal of a rail segment pointing away from the center of the table
+fn outward_normal(start: Vec2, end: Vec2) -> Vec2 {
+    let outward = (end - start).normalize_or_zero().perp();
+    if outward.dot((start + end) / 2.0) < 0.0 {
+        -outward
+    } else {
+        outward
+    }
+}
+
+pub fn closest_point_on_segment(point: Vec2, start: Vec2, end: Vec2) -> Vec2 {
+    let direction = end - start;
+    let length_squared = direction.length_squared();
+    if length_squared <= f32::EPSILON {
+        return start;
+    }
+    let fraction = ((point - start).dot(direction) / length_squared).clamp(0.0, 1.0);
+    start + direction * fraction
+}
+
+pub fn setup_table(
+    mut commands: Commands,
+    textures: Res<Textures>,
+    geometry: Res<TableGeometry>,
+    physics: Res<PuckPhysics>,
+) {
     info!("Setting up table...");
+    spawn_table(
+        &mut commands,
+        textures.table.clone(),
+        &textures,
+        &geometry,
+        &physics,
+    );
+}
+
+/// Spawns the surface, rails, posts and goals, all marked with [`TableEntity`]
+pub fn spawn_table(
+    commands: &mut Commands,
+    surface: Handle<Image>,
+    textures: &Textures,
+    geometry: &TableGeometry,
+    physics: &PuckPhysics,
+) {
     commands
         .spawn()
+        .insert(TableEntity)
         .insert_bundle(SpriteBundle {
             sprite: Sprite {
-                custom_size: Some(Vec2::new(TABLE_LENGTH, TABLE_WIDTH)),
+                custom_size: Some(Vec2::new(geometry.length, geometry.width)),
                 ..default()
             },
-            texture: textures.table.clone(),
+            texture: surface,
             ..default()
         })
         .insert_bundle(TransformBundle::default());
     commands
         .spawn()
+        .insert(TableEntity)
         .insert_bundle(SpriteBundle {
             sprite: Sprite {
                 custom_size: Some(Vec2::new(300.0, 300.0)),
@@ -28,93 +253,192 @@ pub fn setup_table(mut commands: Commands, textures: Res<Textures>) {
         })
         .insert_bundle(TransformBundle::from(Transform::from_xyz(0.0, 0.0, 1.0)));
 
-    commands
-        .spawn()
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite {
-                custom_size: Some(Vec2::new(GOAL_POST_DIAMETER, GOAL_POST_DIAMETER)),
-                ..default()
-            },
-            texture: textures.goal_post.clone(),
-            ..default()
-        })
-        .insert_bundle(TransformBundle::from(Transform::from_xyz(
-            -TABLE_LENGTH / 2.0,
-            GOAL_WIDTH / 2.0 + GOAL_POST_DIAMETER / 2.0,
-            1.0,
-        )));
-    commands
-        .spawn()
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite {
-                custom_size: Some(Vec2::new(GOAL_POST_DIAMETER, GOAL_POST_DIAMETER)),
+    for (team, position) in geometry.goal_posts() {
+        commands
+            .spawn()
+            .insert(TableEntity)
+            .insert(GoalPost { team })
+            .insert(RigidBody::Fixed)
+            .insert(Collider::ball(geometry.goal_post_diameter / 2.0))
+            .insert(surface_restitution(physics.post_restitution))
+            .insert_bundle(SpriteBundle {
+                sprite: Sprite {
+                    custom_size: Some(Vec2::new(
+                        geometry.goal_post_diameter,
+                        geometry.goal_post_diameter,
+                    )),
+                    ..default()
+                },
+                texture: textures.goal_post.clone(),
                 ..default()
-            },
-            texture: textures.goal_post.clone(),
-            ..default()
+            })
+            .insert_bundle(TransformBundle::from(Transform::from_translation(
+                position.extend(1.0),
+            )));
+    }
+
+    for wall in geometry.walls() {
+        commands
+            .spawn()
+            .insert(TableEntity)
+            .insert(Wall)
+            .insert(RigidBody::Fixed)
+            .insert(solid_wall(&wall))
+            .insert(surface_restitution(physics.wall_restitution))
+            .insert_bundle(TransformBundle::default());
+    }
+
+    // The sensors start one puck diameter behind the goal line, so that only pucks which fully
+    // crossed the line between the posts count
+    for team in Team::ALL {
+        let position = geometry.goal_center(team)
+            + Vec2::new(
+                team.side() * (geometry.puck_diameter + GOAL_SENSOR_DEPTH / 2.0),
+                0.0,
+            );
+        commands
+            .spawn()
+            .insert(TableEntity)
+            .insert(Goal { team })
+            .insert(Collider::cuboid(
+                GOAL_SENSOR_DEPTH / 2.0,
+                geometry.goal_width / 2.0,
+            ))
+            .insert(Sensor)
+            .insert(ActiveEvents::COLLISION_EVENTS)
+            .insert_bundle(TransformBundle::from(Transform::from_translation(
+                position.extend(0.0),
+            )));
+    }
+}
+
+/// One box per rail segment with its inner face on the segment, the inner side being the one
+/// facing the center of the table
+///
+/// Boxes are extended by half the thickness where they meet a neighboring segment to close the
+/// corners, but not at the ends of the rail, which would narrow the goal mouths.
+fn solid_wall(rail: &[Vec2]) -> Collider {
+    let boxes = wall_boxes(rail)
+        .into_iter()
+        .map(|(center, angle, half_size)| {
+            (center, angle, Collider::cuboid(half_size.x, half_size.y))
         })
-        .insert_bundle(TransformBundle::from(Transform::from_xyz(
-            -TABLE_LENGTH / 2.0,
-            -GOAL_WIDTH / 2.0 - GOAL_POST_DIAMETER / 2.0,
-            1.0,
-        )));
-    commands
-        .spawn()
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite {
-                custom_size: Some(Vec2::new(GOAL_POST_DIAMETER, GOAL_POST_DIAMETER)),
-                ..default()
-            },
-            texture: textures.goal_post.clone(),
-            ..default()
+        .collect();
+    Collider::compound(boxes)
+}
+
+/// Center, rotation and half size of each box of [`solid_wall`]
+fn wall_boxes(rail: &[Vec2]) -> Vec<(Vec2, f32, Vec2)> {
+    let last = rail.len().saturating_sub(2);
+    rail.windows(2)
+        .enumerate()
+        .map(|(index, segment)| {
+            let direction = (segment[1] - segment[0]).normalize_or_zero();
+            let extension = |is_joint: bool| {
+                if is_joint {
+                    WALL_THICKNESS / 2.0
+                } else {
+                    0.0
+                }
+            };
+            let start = segment[0] - direction * extension(index > 0);
+            let end = segment[1] + direction * extension(index < last);
+            let middle = (start + end) / 2.0;
+            let outward = outward_normal(segment[0], segment[1]);
+            (
+                middle + outward * WALL_THICKNESS / 2.0,
+                direction.y.atan2(direction.x),
+                Vec2::new(start.distance(end) / 2.0, WALL_THICKNESS / 2.0),
+            )
         })
-        .insert_bundle(TransformBundle::from(Transform::from_xyz(
-            TABLE_LENGTH / 2.0,
-            GOAL_WIDTH / 2.0 + GOAL_POST_DIAMETER / 2.0,
-            1.0,
-        )));
-    commands
-        .spawn()
-        .insert_bundle(SpriteBundle {
-            sprite: Sprite {
-                custom_size: Some(Vec2::new(GOAL_POST_DIAMETER, GOAL_POST_DIAMETER)),
-                ..default()
-            },
-            texture: textures.goal_post.clone(),
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn octagon() -> TableGeometry {
+        let rail = |side: f32| {
+            [
+                [-960.0, 160.0],
+                [-960.0, 400.0],
+                [-760.0, 600.0],
+                [760.0, 600.0],
+                [960.0, 400.0],
+                [960.0, 160.0],
+            ]
+            .map(|[x, y]| [x, side * y])
+            .to_vec()
+        };
+        TableGeometry {
+            goal_width: 320.0,
+            rails: Some(vec![rail(1.0), rail(-1.0)]),
             ..default()
-        })
-        .insert_bundle(TransformBundle::from(Transform::from_xyz(
-            TABLE_LENGTH / 2.0,
-            -GOAL_WIDTH / 2.0 - GOAL_POST_DIAMETER / 2.0,
-            1.0,
-        )));
+        }
+    }
 
-    commands
-        .spawn()
-        .insert(RigidBody::Fixed)
-        .insert(Collider::polyline(
-            vec![
-                Vec2::new(-TABLE_LENGTH / 2.0, -GOAL_WIDTH / 2.0),
-                Vec2::new(-TABLE_LENGTH / 2.0, -TABLE_WIDTH / 2.0),
-                Vec2::new(TABLE_LENGTH / 2.0, -TABLE_WIDTH / 2.0),
-                Vec2::new(TABLE_LENGTH / 2.0, -GOAL_WIDTH / 2.0),
-            ],
-            None,
-        ))
-        .insert(Restitution::coefficient(1.0))
-        .insert_bundle(TransformBundle::default());
-    commands
-        .spawn()
-        .insert(RigidBody::Fixed)
-        .insert(Collider::polyline(
-            vec![
-                Vec2::new(-TABLE_LENGTH / 2.0, GOAL_WIDTH / 2.0),
-                Vec2::new(-TABLE_LENGTH / 2.0, TABLE_WIDTH / 2.0),
-                Vec2::new(TABLE_LENGTH / 2.0, TABLE_WIDTH / 2.0),
-                Vec2::new(TABLE_LENGTH / 2.0, GOAL_WIDTH / 2.0),
-            ],
-            None,
-        ))
-        .insert(Restitution::coefficient(1.0))
-        .insert_bundle(TransformBundle::default());
+    #[test]
+    fn goal_covers_mouth_behind_goal_line() {
+        let geometry = TableGeometry::default();
+        assert!(geometry.is_in_goal(Vec2::new(-980.0, 0.0)));
+        assert!(geometry.is_in_goal(Vec2::new(985.0, 230.0)));
+        assert!(!geometry.is_in_goal(Vec2::new(-980.0, 300.0)));
+        assert!(!geometry.is_in_goal(Vec2::new(900.0, 0.0)));
+    }
+
+    #[test]
+    fn wall_boxes_close_corners_but_not_goal_mouths() {
+        let geometry = TableGeometry::default();
+        let lower_rail = &geometry.walls()[0];
+        let boxes = wall_boxes(lower_rail);
+        assert_eq!(boxes.len(), 3);
+
+        // The long side is extended at both corners and lies behind the rail
+        let (center, _, half_size) = boxes[1];
+        assert!(center.distance(Vec2::new(0.0, -650.0)) < 1e-3);
+        assert!((half_size.x - 1010.0).abs() < 1e-3);
+
+        // The end at the goal mouth stays at the post
+        let (center, _, half_size) = boxes[0];
+        assert!(center.distance(Vec2::new(-1010.0, -425.0)) < 1e-3);
+        assert!((half_size.x - 225.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn octagon_corners_are_outside() {
+        let geometry = octagon();
+        assert!(geometry.contains(Vec2::ZERO));
+        assert!(geometry.contains(Vec2::new(-950.0, 0.0)));
+        assert!(!geometry.contains(Vec2::new(-900.0, 550.0)));
+        assert!(!geometry.contains(Vec2::new(900.0, -550.0)));
+    }
+
+    #[test]
+    fn clamped_stick_stays_off_octagon_corners() {
+        let geometry = octagon();
+        let radius = geometry.stick_diameter / 2.0;
+        for corner in [Vec2::new(-900.0, 550.0), Vec2::new(800.0, -500.0)] {
+            let clamped = geometry.clamp_inside(corner, radius);
+            assert!(geometry.contains(clamped));
+            assert!(
+                geometry.distance_to_walls(clamped) >= radius - 0.1,
+                "{} clamped to {}",
+                corner,
+                clamped
+            );
+        }
+        // In front of a goal mouth only the goal line limits the stick
+        let clamped = geometry.clamp_inside(Vec2::new(-950.0, 0.0), radius);
+        assert!(clamped.distance(Vec2::new(-900.0, 0.0)) < 1e-3);
+    }
+
+    #[test]
+    fn default_table_clamps_to_rectangle() {
+        let geometry = TableGeometry::default();
+        assert_eq!(
+            geometry.clamp_inside(Vec2::new(2000.0, -700.0), 60.0),
+            Vec2::new(900.0, -540.0)
+        );
+    }
 }
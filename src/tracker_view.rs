@@ -0,0 +1,255 @@
+use std::f32::consts::TAU;
+
+use bevy::{
+    diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+};
+use bevy_prototype_debug_lines::DebugLines;
+use iyes_loopless::state::{CurrentState, NextState};
+
+use crate::{
+    app_state::{AppState, GameState},
+    assets::Fonts,
+    classification::ClusterClass,
+    lidar_communication::{Cluster, LidarScan},
+    table::TableGeometry,
+    tracking::{TrackId, Tracks},
+};
+
+/// Marks every entity spawned for the tracker view, despawned when leaving it
+#[derive(Component)]
+pub struct TrackerViewUi;
+
+#[derive(Component)]
+pub struct TrackerStatsUi;
+
+#[derive(Component)]
+pub struct TrackLabel(TrackId);
+
+pub fn toggle_tracker_view(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    app_state: Res<CurrentState<AppState>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::F1) {
+        return;
+    }
+    match app_state.0 {
+        AppState::Game(_) => commands.insert_resource(NextState(AppState::Tracker)),
+        AppState::Tracker => {
+            commands.insert_resource(NextState(AppState::Game(GameState::Running)))
+        }
+        _ => {}
+    }
+}
+
+pub fn setup_tracker_view(mut commands: Commands, fonts: Res<Fonts>, geometry: Res<TableGeometry>) {
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: fonts.arial.clone(),
+                    font_size: 40.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_alignment(TextAlignment::BOTTOM_LEFT),
+            transform: Transform::from_xyz(
+                -geometry.length / 2.0,
+                geometry.width / 2.0 + 20.0,
+                10.0,
+            ),
+            ..default()
+        })
+        .insert(TrackerStatsUi)
+        .insert(TrackerViewUi);
+}
+
+pub fn cleanup_tracker_view(mut commands: Commands, entities: Query<Entity, With<TrackerViewUi>>) {
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn class_color(class: ClusterClass) -> Color {
+    match class {
+        ClusterClass::Stick => Color::GREEN,
+        ClusterClass::HandOrArm => Color::YELLOW,
+        ClusterClass::Unknown => Color::GRAY,
+    }
+}
+
+/// 2-sigma ellipse of the cluster's point covariance
+fn draw_covariance_ellipse(lines: &mut DebugLines, cluster: &Cluster) {
+    let covariance = cluster.features.covariance;
+    let (xx, xy, yy) = (
+        covariance.x_axis.x,
+        covariance.x_axis.y,
+        covariance.y_axis.y,
+    );
+    let trace = xx + yy;
+    let discriminant = ((xx - yy) * (xx - yy) + 4.0 * xy * xy).sqrt();
+    let major = ((trace + discriminant) / 2.0).max(0.0).sqrt() * 2.0;
+    let minor = ((trace - discriminant) / 2.0).max(0.0).sqrt() * 2.0;
+    let rotation = Mat2::from_angle(0.5 * (2.0 * xy).atan2(xx - yy));
+
+    let segments = 24;
+    let point_at = |index: usize| {
+        let angle = index as f32 / segments as f32 * TAU;
+        cluster.center + rotation * Vec2::new(major * angle.cos(), minor * angle.sin())
+    };
+    for index in 0..segments {
+        lines.line_colored(
+            point_at(index).extend(3.0),
+            point_at(index + 1).extend(3.0),
+            0.0,
+            class_color(cluster.class),
+        );
+    }
+}
+
+pub fn draw_scan(
+    mut scans: EventReader<LidarScan>,
+    mut last_scan: Local<Option<LidarScan>>,
+    mut lines: ResMut<DebugLines>,
+) {
+    if let Some(scan) = scans.iter().last() {
+        *last_scan = Some(scan.clone());
+    }
+    let scan = match &*last_scan {
+        Some(scan) => scan,
+        None => return,
+    };
+
+    let cross_size = 6.0;
+    for cluster in &scan.clusters {
+        let color = class_color(cluster.class);
+        for point in &cluster.points {
+            lines.line_colored(
+                (*point - Vec2::splat(cross_size)).extend(3.0),
+                (*point + Vec2::splat(cross_size)).extend(3.0),
+                0.0,
+                color,
+            );
+            lines.line_colored(
+                (*point + Vec2::new(-cross_size, cross_size)).extend(3.0),
+                (*point + Vec2::new(cross_size, -cross_size)).extend(3.0),
+                0.0,
+                color,
+            );
+        }
+        draw_covariance_ellipse(&mut lines, cluster);
+    }
+}
+
+pub fn draw_tracks(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    tracks: Res<Tracks>,
+    mut lines: ResMut<DebugLines>,
+    mut labels: Query<(Entity, &TrackLabel, &mut Transform, &mut Text)>,
+) {
+    // Velocity vectors show the distance covered in the next 200 ms
+    let velocity_scale = 0.2;
+    for track in &tracks.tracks {
+        let color = if track.occluded {
+            Color::PURPLE
+        } else {
+            Color::CYAN
+        };
+        lines.line_colored(
+            track.position.extend(4.0),
+            (track.position + track.velocity * velocity_scale).extend(4.0),
+            0.0,
+            color,
+        );
+    }
+
+    for (entity, label, mut transform, mut text) in &mut labels {
+        match tracks.get(label.0) {
+            Some(track) => {
+                transform.translation = (track.position + Vec2::new(0.0, 80.0)).extend(10.0);
+                text.sections[0].value = if track.occluded {
+                    format!("#{} (occluded)", track.id)
+                } else {
+                    format!("#{}", track.id)
+                };
+            }
+            None => commands.entity(entity).despawn_recursive(),
+        }
+    }
+
+    let labelled: Vec<_> = labels.iter().map(|(_, label, _, _)| label.0).collect();
+    for track in tracks
+        .tracks
+        .iter()
+        .filter(|track| !labelled.contains(&track.id))
+    {
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::from_section(
+                    format!("#{}", track.id),
+                    TextStyle {
+                        font: fonts.arial.clone(),
+                        font_size: 32.0,
+                        color: Color::WHITE,
+                    },
+                )
+                .with_alignment(TextAlignment::CENTER),
+                transform: Transform::from_translation(
+                    (track.position + Vec2::new(0.0, 80.0)).extend(10.0),
+                ),
+                ..default()
+            })
+            .insert(TrackLabel(track.id))
+            .insert(TrackerViewUi);
+    }
+}
+
+/// Smoothed scan statistics shown in the tracker view
+#[derive(Default)]
+pub struct ScanStats {
+    last_scan_time: Option<f64>,
+    scan_rate: f64,
+    latency_ms: f64,
+    cluster_count: usize,
+}
+
+pub fn update_tracker_stats(
+    diagnostics: Res<Diagnostics>,
+    mut scans: EventReader<LidarScan>,
+    mut scan_stats: Local<ScanStats>,
+    tracks: Res<Tracks>,
+    mut stats: Query<&mut Text, With<TrackerStatsUi>>,
+) {
+    for scan in scans.iter() {
+        if let Some(last_scan_time) = scan_stats.last_scan_time {
+            let interval = scan.time - last_scan_time;
+            if interval > 0.0 {
+                scan_stats.scan_rate = 0.9 * scan_stats.scan_rate + 0.1 / interval;
+            }
+        }
+        scan_stats.last_scan_time = Some(scan.time);
+        scan_stats.latency_ms =
+            0.9 * scan_stats.latency_ms + 0.1 * scan.latency.as_secs_f64() * 1000.0;
+        scan_stats.cluster_count = scan.clusters.len();
+    }
+
+    let fps = diagnostics
+        .get(FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|fps| fps.smoothed())
+        .unwrap_or_default();
+    let occluded = tracks.tracks.iter().filter(|track| track.occluded).count();
+    for mut text in &mut stats {
+        text.sections[0].value = format!(
+            "{:.0} FPS | scans {:.1} Hz | latency {:.1} ms | {} clusters | {} tracks ({} occluded)",
+            fps,
+            scan_stats.scan_rate,
+            scan_stats.latency_ms,
+            scan_stats.cluster_count,
+            tracks.tracks.len(),
+            occluded
+        );
+    }
+}
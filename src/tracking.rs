@@ -0,0 +1,304 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    classification::ClusterClass,
+    lidar_communication::{Cluster, LidarScan},
+    table::TableGeometry,
+};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TrackingSettings {
+    /// Maximum distance in pixels between a predicted track and a cluster to associate them
+    pub gate_distance: f32,
+    /// Seconds a visible but unmatched track is coasted before it is dropped
+    pub max_coast_time: f64,
+    /// Seconds an occluded track is coasted before it is dropped
+    pub max_occluded_time: f64,
+    /// Weight of the newest velocity measurement, between 0.0 and 1.0
+    pub velocity_smoothing: f32,
+    /// Per-second decay of the velocity while coasting, between 0.0 and 1.0
+    pub coast_velocity_decay: f32,
+}
+
+impl Default for TrackingSettings {
+    fn default() -> Self {
+        Self {
+            gate_distance: 150.0,
+            max_coast_time: 0.2,
+            max_occluded_time: 1.5,
+            velocity_smoothing: 0.5,
+            coast_velocity_decay: 0.5,
+        }
+    }
+}
+
+pub type TrackId = usize;
+
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub id: TrackId,
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub last_seen: f64,
+    pub last_update: f64,
+    pub occluded: bool,
+}
+
+#[derive(Default)]
+pub struct Tracks {
+    pub tracks: Vec<Track>,
+    next_id: TrackId,
+}
+
+impl Tracks {
+    pub fn get(&self, id: TrackId) -> Option<&Track> {
+        self.tracks.iter().find(|track| track.id == id)
+    }
+
+    fn update(&mut self, scan: &LidarScan, settings: &TrackingSettings, geometry: &TableGeometry) {
+        let sticks: Vec<_> = scan
+            .clusters
+            .iter()
+            .filter(|cluster| cluster.class == ClusterClass::Stick)
+            .collect();
+
+        let predictions: Vec<_> = self
+            .tracks
+            .iter()
+            .map(|track| track.position + track.velocity * (scan.time - track.last_update) as f32)
+            .collect();
+
+        let mut candidates = Vec::new();
+        for (track_index, prediction) in predictions.iter().enumerate() {
+            for (cluster_index, cluster) in sticks.iter().enumerate() {
+                let distance = (cluster.center - *prediction).length();
+                if distance < settings.gate_distance {
+                    candidates.push((distance, track_index, cluster_index));
+                }
+            }
+        }
+        candidates.sort_unstable_by(|left, right| left.0.total_cmp(&right.0));
+
+        let mut track_matches = vec![None; self.tracks.len()];
+        let mut cluster_matched = vec![false; sticks.len()];
+        for (_, track_index, cluster_index) in candidates {
+            if track_matches[track_index].is_none() && !cluster_matched[cluster_index] {
+                track_matches[track_index] = Some(cluster_index);
+                cluster_matched[cluster_index] = true;
+            }
+        }
+
+        let origin = geometry.lidar_origin();
+        let shadows: Vec<_> = scan
+            .clusters
+            .iter()
+            .map(|cluster| Shadow::cast_by(cluster, origin))
+            .collect();
+        for ((track, prediction), matched) in
+            self.tracks.iter_mut().zip(predictions).zip(track_matches)
+        {
+            let dt = (scan.time - track.last_update) as f32;
+            track.last_update = scan.time;
+            match matched {
+                Some(cluster_index) => {
+                    let center = sticks[cluster_index].center;
+                    if dt > 0.0 {
+                        let measured_velocity = (center - track.position) / dt;
+                        track.velocity = track
+                            .velocity
+                            .lerp(measured_velocity, settings.velocity_smoothing);
+                    }
+                    track.position = center;
+                    track.last_seen = scan.time;
+                    track.occluded = false;
+                }
+                None => {
+                    let half_size = geometry.half_size();
+                    track.position = prediction.clamp(-half_size, half_size);
+                    track.velocity *= settings.coast_velocity_decay.powf(dt);
+                    track.occluded = shadows.iter().any(|shadow| {
+                        shadow.hides(track.position, origin, geometry.stick_diameter / 2.0)
+                    });
+                }
+            }
+        }
+
+        self.tracks.retain(|track| {
+            let max_age = if track.occluded {
+                settings.max_occluded_time
+            } else {
+                settings.max_coast_time
+            };
+            scan.time - track.last_seen <= max_age
+        });
+
+        for (cluster, _) in sticks
+            .iter()
+            .zip(cluster_matched)
+            .filter(|(_, matched)| !matched)
+        {
+            self.tracks.push(Track {
+                id: self.next_id,
+                position: cluster.center,
+                velocity: Vec2::ZERO,
+                last_seen: scan.time,
+                last_update: scan.time,
+                occluded: false,
+            });
+            self.next_id += 1;
+        }
+    }
+}
+
+/// Region behind a cluster as seen from the sensor, in polar coordinates around the sensor
+struct Shadow {
+    min_angle: f32,
+    max_angle: f32,
+    range: f32,
+}
+
+impl Shadow {
+    fn cast_by(cluster: &Cluster, origin: Vec2) -> Self {
+        cluster.points.iter().fold(
+            Self {
+                min_angle: f32::INFINITY,
+                max_angle: f32::NEG_INFINITY,
+                range: f32::INFINITY,
+            },
+            |shadow, &point| {
+                let offset = point - origin;
+                let angle = offset.y.atan2(offset.x);
+                Self {
+                    min_angle: shadow.min_angle.min(angle),
+                    max_angle: shadow.max_angle.max(angle),
+                    range: shadow.range.min(offset.length()),
+                }
+            },
+        )
+    }
+
+    /// Whether a stick centered at `position` would be (partially) hidden behind this shadow
+    fn hides(&self, position: Vec2, origin: Vec2, stick_radius: f32) -> bool {
+        let offset = position - origin;
+        let range = offset.length();
+        if range <= self.range {
+            return false;
+        }
+        let angle = offset.y.atan2(offset.x);
+        let margin = (stick_radius / range).min(1.0).asin();
+        (self.min_angle - margin..=self.max_angle + margin).contains(&angle)
+    }
+}
+
+pub fn update_tracks(
+    mut scans: EventReader<LidarScan>,
+    mut tracks: ResMut<Tracks>,
+    settings: Res<TrackingSettings>,
+    geometry: Res<TableGeometry>,
+) {
+    for scan in scans.iter() {
+        tracks.update(scan, &settings, &geometry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::classification::ClusterFeatures;
+
+    /// Points the lidar sees on the near side of a stick at `center`, looking up from below
+    fn stick(center: Vec2) -> Cluster {
+        Cluster {
+            center,
+            points: vec![
+                center + Vec2::new(-30.0, 0.0),
+                center + Vec2::new(0.0, -10.0),
+                center + Vec2::new(30.0, 0.0),
+            ],
+            features: ClusterFeatures::default(),
+            class: ClusterClass::Stick,
+        }
+    }
+
+    fn scan(time: f64, clusters: Vec<Cluster>) -> LidarScan {
+        LidarScan {
+            time,
+            latency: Duration::ZERO,
+            clusters,
+        }
+    }
+
+    #[test]
+    fn unmatched_track_coasts_until_max_coast_time() {
+        let settings = TrackingSettings::default();
+        let geometry = TableGeometry::default();
+        let mut tracks = Tracks::default();
+        tracks.update(&scan(0.0, vec![stick(Vec2::ZERO)]), &settings, &geometry);
+        tracks.update(
+            &scan(0.1, vec![stick(Vec2::new(30.0, 0.0))]),
+            &settings,
+            &geometry,
+        );
+        let track = &tracks.tracks[0];
+        assert!((track.velocity - Vec2::new(150.0, 0.0)).length() < 1e-3);
+
+        // Nothing hides the stick, so it is only coasted on its predicted motion for a moment
+        tracks.update(&scan(0.2, vec![]), &settings, &geometry);
+        let track = &tracks.tracks[0];
+        assert_eq!(track.id, 0);
+        assert!(!track.occluded);
+        assert!((track.position - Vec2::new(45.0, 0.0)).length() < 1e-3);
+        assert!(track.velocity.x < 150.0);
+
+        tracks.update(&scan(0.35, vec![]), &settings, &geometry);
+        assert!(tracks.tracks.is_empty());
+    }
+
+    #[test]
+    fn track_behind_another_stick_is_kept_until_max_occluded_time() {
+        let settings = TrackingSettings::default();
+        let geometry = TableGeometry::default();
+        let far = Vec2::new(-100.0, 300.0);
+        let near = Vec2::new(0.0, -300.0);
+        let mut tracks = Tracks::default();
+        tracks.update(
+            &scan(0.0, vec![stick(far), stick(near)]),
+            &settings,
+            &geometry,
+        );
+        assert_eq!(tracks.tracks.len(), 2);
+
+        // The far stick moves behind the near stick and is no longer seen, its track stays at
+        // the last known position
+        tracks.update(&scan(0.1, vec![stick(near)]), &settings, &geometry);
+        let hidden = tracks.get(0).unwrap();
+        assert!(hidden.occluded);
+        assert_eq!(hidden.position, far);
+        assert_eq!(tracks.get(1).unwrap().position, near);
+
+        tracks.update(&scan(1.0, vec![stick(near)]), &settings, &geometry);
+        assert!(tracks.get(0).is_some());
+        tracks.update(&scan(1.7, vec![stick(near)]), &settings, &geometry);
+        assert!(tracks.get(0).is_none());
+        assert!(tracks.get(1).is_some());
+    }
+
+    #[test]
+    fn shadow_hides_positions_behind_the_cluster() {
+        let geometry = TableGeometry::default();
+        let origin = geometry.lidar_origin();
+        let shadow = Shadow::cast_by(&stick(Vec2::new(0.0, -300.0)), origin);
+        let radius = geometry.stick_diameter / 2.0;
+
+        assert!(shadow.hides(Vec2::new(0.0, 300.0), origin, radius));
+        // Outside the shadow, but close enough to its edge to be partially hidden
+        assert!(shadow.hides(Vec2::new(120.0, 300.0), origin, radius));
+        assert!(!shadow.hides(Vec2::new(250.0, 300.0), origin, radius));
+        // In front of the cluster
+        assert!(!shadow.hides(Vec2::new(0.0, -400.0), origin, radius));
+    }
+}
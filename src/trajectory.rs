@@ -1,47 +1,51 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 
 use bevy::prelude::*;
 use bevy_prototype_debug_lines::DebugLines;
 
-use crate::lidar_communication::{process_lidar_message, Cluster, LidarChannel, LidarSettings};
+use crate::{
+    lidar_communication::LidarScan,
+    tracking::{TrackId, Tracks},
+};
 
+const MAX_BUFFER_LENGTH: usize = 100;
+
+/// Timestamped recent positions of one track
 #[derive(Default)]
 pub struct Buffer {
-    data: VecDeque<Vec2>,
+    pub data: VecDeque<(f64, Vec2)>,
 }
 
-pub fn track_object(
-    lidar_settings: Res<LidarSettings>,
-    lidar_channel: Res<LidarChannel>,
-    mut buffer: Local<Buffer>,
-    mut lines: ResMut<DebugLines>,
+#[derive(Default)]
+pub struct Trajectories {
+    pub buffers: HashMap<TrackId, Buffer>,
+}
+
+pub fn record_trajectories(
+    mut scans: EventReader<LidarScan>,
+    tracks: Res<Tracks>,
+    mut trajectories: ResMut<Trajectories>,
 ) {
-    for positions in lidar_channel.receiver.try_iter() {
-        if buffer.data.len() > 100 {
-            buffer.data.pop_front();
-        }
-        if positions.is_empty() {
-            continue;
-        }
-        let mut points: Vec<_> = process_lidar_message(positions, lidar_settings.pixels_per_meter);
-        let mut clusters = Vec::<Cluster>::new();
-        for &point in &points {
-            let is_close_to_cluster = clusters
-                .iter_mut()
-                .find(|cluster| (cluster.center - point).length() < 100.0);
-            if let Some(cluster) = is_close_to_cluster {
-                cluster.add(point);
-                continue;
+    for scan in scans.iter() {
+        for track in &tracks.tracks {
+            let buffer = trajectories.buffers.entry(track.id).or_default();
+            if buffer.data.len() >= MAX_BUFFER_LENGTH {
+                buffer.data.pop_front();
             }
-            clusters.push(Cluster::new(point));
-        }
-        if let Some(cluster) = clusters.first() {
-            buffer.data.push_back(cluster.center)
+            buffer.data.push_back((scan.time, track.position));
         }
     }
-    for points in buffer.data.make_contiguous().windows(2) {
-        let left = points[0];
-        let right = points[1];
-        lines.line_colored(left.extend(1.0), right.extend(1.0), 10.0, Color::RED);
+    trajectories
+        .buffers
+        .retain(|&id, _| tracks.get(id).is_some());
+}
+
+pub fn draw_trajectories(mut trajectories: ResMut<Trajectories>, mut lines: ResMut<DebugLines>) {
+    for buffer in trajectories.buffers.values_mut() {
+        for points in buffer.data.make_contiguous().windows(2) {
+            let (_, left) = points[0];
+            let (_, right) = points[1];
+            lines.line_colored(left.extend(1.0), right.extend(1.0), 0.0, Color::RED);
+        }
     }
 }
@@ -1,35 +1,256 @@
 use std::f32::consts::PI;
 
 use bevy::prelude::*;
+use serde::Deserialize;
 
-use crate::{assets::Fonts, score::Score, SCORE_FONT_SIZE, TABLE_WIDTH};
+use crate::{
+    assets::Fonts,
+    player::Team,
+    rules::{Match, MatchSettings},
+    score::{GoalScored, Score},
+    serve::Serve,
+    table::TableGeometry,
+    TIMER_FONT_SIZE,
+};
+
+const SCORE_COLOR: Color = Color::BLACK;
+
+/// Where a score display is drawn and which way it faces
+#[derive(Clone, Debug, Deserialize)]
+pub struct ScoreDisplayLayout {
+    /// Team whose goals are shown first
+    pub team: Team,
+    /// Center of the display in table space
+    pub position: [f32; 2],
+    /// Degrees counterclockwise, 0.0 reads correctly from the lower long edge
+    pub rotation: f32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ScoreDisplaySettings {
+    pub displays: Vec<ScoreDisplayLayout>,
+    pub font_size: f32,
+    /// Seconds the displays flash in the scoring team's color after a goal
+    pub goal_animation_duration: f32,
+}
+
+impl Default for ScoreDisplaySettings {
+    fn default() -> Self {
+        // Players stand at the goal ends, facing each other across the table
+        Self {
+            displays: vec![
+                ScoreDisplayLayout {
+                    team: Team::Left,
+                    position: [-480.0, 0.0],
+                    rotation: -90.0,
+                },
+                ScoreDisplayLayout {
+                    team: Team::Right,
+                    position: [480.0, 0.0],
+                    rotation: 90.0,
+                },
+            ],
+            font_size: 300.0,
+            goal_animation_duration: 1.2,
+        }
+    }
+}
+
+/// Score as seen by `team`, its own goals first
+#[derive(Component)]
+pub struct ScoreUi {
+    pub team: Team,
+    /// Seconds left of the goal animation and the team that scored
+    goal_animation: Option<(Team, f32)>,
+}
 
 #[derive(Component)]
-pub struct ScoreUi;
+pub struct GameTimeUi;
 
 #[derive(Component)]
-struct GameTimeUi;
+pub struct ServeCountdownUi;
 
-pub fn setup_ui(mut commands: Commands, fonts: Res<Fonts>) {
+/// Marks every entity spawned for the pause overlay, despawned when the game resumes
+#[derive(Component)]
+pub struct PauseUi;
+
+pub fn setup_ui(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    geometry: Res<TableGeometry>,
+    score_settings: Res<ScoreDisplaySettings>,
+) {
     // Setting up the score
     let score_text_style = TextStyle {
         font: fonts.arial.clone(),
-        font_size: SCORE_FONT_SIZE,
-        color: Color::BLACK,
+        font_size: score_settings.font_size,
+        color: SCORE_COLOR,
     };
+    for layout in &score_settings.displays {
+        commands
+            .spawn_bundle(Text2dBundle {
+                text: Text::from_section("0:0", score_text_style.clone())
+                    .with_alignment(TextAlignment::CENTER),
+                transform: Transform::from_translation(Vec2::from(layout.position).extend(10.0))
+                    .with_rotation(Quat::from_rotation_z(layout.rotation.to_radians())),
+                ..default()
+            })
+            .insert(ScoreUi {
+                team: layout.team,
+                goal_animation: None,
+            });
+    }
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: fonts.arial.clone(),
+                    font_size: TIMER_FONT_SIZE,
+                    color: Color::WHITE,
+                },
+            )
+            .with_alignment(TextAlignment::BOTTOM_CENTER),
+            transform: Transform::from_xyz(0.0, geometry.width / 2.0 + 20.0, 10.0),
+            ..default()
+        })
+        .insert(GameTimeUi);
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: fonts.arial.clone(),
+                    font_size: 120.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_alignment(TextAlignment::CENTER),
+            ..default()
+        })
+        .insert(ServeCountdownUi);
+}
+
+pub fn update_score_ui(
+    time: Res<Time>,
+    settings: Res<ScoreDisplaySettings>,
+    score: Res<Score>,
+    mut goal_events: EventReader<GoalScored>,
+    mut scores: Query<(&mut ScoreUi, &mut Text, &mut Transform)>,
+) {
+    let scoring_team = goal_events.iter().last().map(|goal| goal.team);
+    for (mut display, mut text, mut transform) in &mut scores {
+        let team = display.team;
+        text.sections[0].value = format!("{}:{}", score.goals(team), score.goals(team.opponent()));
+
+        if let Some(scoring_team) = scoring_team {
+            display.goal_animation = Some((scoring_team, settings.goal_animation_duration));
+        }
+        // Pulses in the scoring team's color, fading back to the normal score
+        let (color, scale) = match &mut display.goal_animation {
+            Some((scoring_team, remaining)) => {
+                *remaining -= time.delta_seconds();
+                let progress = 1.0 - (*remaining / settings.goal_animation_duration).max(0.0);
+                let pulse = (progress * PI * 3.0).sin().abs() * (1.0 - progress);
+                let color = mix(team_color(*scoring_team), SCORE_COLOR, progress);
+                (color, 1.0 + 0.3 * pulse)
+            }
+            None => (SCORE_COLOR, 1.0),
+        };
+        if matches!(display.goal_animation, Some((_, remaining)) if remaining <= 0.0) {
+            display.goal_animation = None;
+        }
+        text.sections[0].style.color = color;
+        transform.scale = Vec3::new(scale, scale, 1.0);
+    }
+}
+
+fn team_color(team: Team) -> Color {
+    match team {
+        Team::Left => Color::rgb(0.1, 0.3, 0.9),
+        Team::Right => Color::rgb(0.9, 0.2, 0.1),
+    }
+}
+
+fn mix(from: Color, to: Color, fraction: f32) -> Color {
+    let channel = |from: f32, to: f32| from + (to - from) * fraction;
+    Color::rgb(
+        channel(from.r(), to.r()),
+        channel(from.g(), to.g()),
+        channel(from.b(), to.b()),
+    )
+}
+
+pub fn update_game_time_ui(
+    mut timers: Query<&mut Text, With<GameTimeUi>>,
+    game: Res<Match>,
+    settings: Res<MatchSettings>,
+) {
+    let label = game.period_label(&settings);
+    let mut text = timers.single_mut();
+    text.sections[0].value = match game.remaining {
+        Some(remaining) => {
+            let seconds = remaining.ceil() as u32;
+            format!("{label}  {}:{:02}", seconds / 60, seconds % 60)
+        }
+        None => label,
+    };
+}
+
+/// Shows the remaining seconds of a serve next to the held puck
+pub fn update_serve_countdown_ui(
+    mut countdowns: Query<(&mut Text, &mut Transform), With<ServeCountdownUi>>,
+    serves: Query<(&Serve, &Transform), Without<ServeCountdownUi>>,
+) {
+    let (mut text, mut transform) = countdowns.single_mut();
+    match serves.iter().next() {
+        Some((serve, puck_transform)) => {
+            text.sections[0].value = format!("{}", serve.remaining.max(0.0).ceil());
+            transform.translation =
+                puck_transform.translation.truncate().extend(10.0) + Vec3::new(0.0, 100.0, 0.0);
+        }
+        None => text.sections[0].value.clear(),
+    }
+}
+
+pub fn setup_pause_overlay(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    geometry: Res<TableGeometry>,
+) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgba(0.0, 0.0, 0.0, 0.6),
+                custom_size: Some(Vec2::new(geometry.length, geometry.width)),
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, 15.0),
+            ..default()
+        })
+        .insert(PauseUi);
     commands
         .spawn_bundle(Text2dBundle {
-            text: Text::from_section("0:0", score_text_style)
-                .with_alignment(TextAlignment::TOP_CENTER),
-            transform: Transform::from_xyz(0.0, -TABLE_WIDTH / 2.0 + 50.0, 10.0)
-                .with_rotation(Quat::from_rotation_z(PI)),
+            text: Text::from_section(
+                "Paused\nSpace to resume, Escape for the lobby",
+                TextStyle {
+                    font: fonts.arial.clone(),
+                    font_size: 80.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_alignment(TextAlignment::CENTER),
+            transform: Transform::from_xyz(0.0, 0.0, 20.0),
             ..default()
         })
-        .insert(ScoreUi);
+        .insert(PauseUi);
 }
 
-pub fn update_score_ui(mut scores: Query<&mut Text, With<ScoreUi>>, score: Res<Score>) {
-    let score_info = format!("{}:{}", score.right, score.left);
-    let mut text = scores.single_mut();
-    text.sections[0].value = score_info;
+pub fn cleanup_pause_overlay(mut commands: Commands, entities: Query<Entity, With<PauseUi>>) {
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
 }
@@ -0,0 +1,172 @@
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::DebugLines;
+use serde::Deserialize;
+
+use crate::{player::Team, table::TableGeometry};
+
+/// Halvings of a step leaving a zone, enough for a step of a few hundred pixels to end within a
+/// pixel of the zone's edge
+const STEP_BISECTIONS: usize = 10;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ZoneSettings {
+    /// Keep each stick on its own team's side of the center line
+    pub restrict_to_half: bool,
+    pub show: bool,
+}
+
+impl Default for ZoneSettings {
+    fn default() -> Self {
+        Self {
+            restrict_to_half: true,
+            show: true,
+        }
+    }
+}
+
+/// Area of the table in which a player's stick may pick up tracks
+#[derive(Component, Clone, Debug)]
+pub struct PlayerZone {
+    pub polygon: Vec<Vec2>,
+}
+
+impl PlayerZone {
+    pub fn new(polygon: &[[f32; 2]]) -> Self {
+        Self {
+            polygon: polygon.iter().map(|&point| Vec2::from(point)).collect(),
+        }
+    }
+
+    pub fn half(team: Team, geometry: &TableGeometry) -> Self {
+        let half_size = geometry.half_size();
+        let side = team.side();
+        Self {
+            polygon: vec![
+                Vec2::new(0.0, -half_size.y),
+                Vec2::new(side * half_size.x, -half_size.y),
+                Vec2::new(side * half_size.x, half_size.y),
+                Vec2::new(0.0, half_size.y),
+            ],
+        }
+    }
+
+    pub fn contains(&self, point: Vec2) -> bool {
+        polygon_contains(&self.polygon, point)
+    }
+
+    /// Farthest point on the way from `start` to `end` that is still inside the zone, found by
+    /// bisection. A start outside the zone takes the whole step, so that it can get back in.
+    pub fn limit_step(&self, start: Vec2, end: Vec2) -> Vec2 {
+        if self.contains(end) || !self.contains(start) {
+            return end;
+        }
+        let (mut inside, mut outside) = (start, end);
+        for _ in 0..STEP_BISECTIONS {
+            let middle = (inside + outside) / 2.0;
+            if self.contains(middle) {
+                inside = middle;
+            } else {
+                outside = middle;
+            }
+        }
+        inside
+    }
+}
+
+/// Even-odd rule point-in-polygon test
+pub fn polygon_contains(polygon: &[Vec2], point: Vec2) -> bool {
+    let mut inside = false;
+    let edges = polygon.iter().zip(polygon.iter().cycle().skip(1));
+    for (&start, &end) in edges {
+        if (start.y > point.y) != (end.y > point.y) {
+            let crossing_x = start.x + (point.y - start.y) / (end.y - start.y) * (end.x - start.x);
+            if point.x < crossing_x {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+/// Clamps a stick position to its own half so that it cannot cross the center line
+pub fn restrict_to_half(position: Vec2, team: Team, geometry: &TableGeometry) -> Vec2 {
+    let margin = geometry.stick_diameter / 2.0;
+    let x = match team {
+        Team::Left => position.x.min(-margin),
+        Team::Right => position.x.max(margin),
+    };
+    Vec2::new(x, position.y)
+}
+
+pub fn draw_player_zones(
+    settings: Res<ZoneSettings>,
+    mut lines: ResMut<DebugLines>,
+    zones: Query<(&PlayerZone, &Team)>,
+) {
+    if !settings.show {
+        return;
+    }
+    for (zone, team) in &zones {
+        let color = match team {
+            Team::Left => Color::CYAN,
+            Team::Right => Color::ORANGE,
+        };
+        let edges = zone.polygon.iter().zip(zone.polygon.iter().cycle().skip(1));
+        for (&start, &end) in edges {
+            lines.line_colored(start.extend(1.5), end.extend(1.5), 0.0, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contains_points_inside_a_concave_zone() {
+        // L-shaped zone with the upper right quarter cut out
+        let zone = PlayerZone::new(&[
+            [0.0, 0.0],
+            [200.0, 0.0],
+            [200.0, 100.0],
+            [100.0, 100.0],
+            [100.0, 200.0],
+            [0.0, 200.0],
+        ]);
+        assert!(zone.contains(Vec2::new(50.0, 50.0)));
+        assert!(zone.contains(Vec2::new(150.0, 50.0)));
+        assert!(zone.contains(Vec2::new(50.0, 150.0)));
+        assert!(!zone.contains(Vec2::new(150.0, 150.0)));
+        assert!(!zone.contains(Vec2::new(-10.0, 50.0)));
+        assert!(!zone.contains(Vec2::new(50.0, 250.0)));
+    }
+
+    #[test]
+    fn half_zone_covers_own_side() {
+        let geometry = TableGeometry::default();
+        let zone = PlayerZone::half(Team::Left, &geometry);
+        assert!(zone.contains(Vec2::new(-300.0, 200.0)));
+        assert!(!zone.contains(Vec2::new(300.0, 200.0)));
+    }
+
+    #[test]
+    fn step_leaving_zone_ends_at_its_edge() {
+        let zone = PlayerZone::new(&[[0.0, 0.0], [200.0, 0.0], [200.0, 200.0], [0.0, 200.0]]);
+        let start = Vec2::new(100.0, 100.0);
+        let inside = Vec2::new(150.0, 100.0);
+        assert_eq!(zone.limit_step(start, inside), inside);
+
+        let limited = zone.limit_step(start, Vec2::new(300.0, 100.0));
+        assert!(zone.contains(limited));
+        assert!(limited.x > 199.0, "stopped at {}", limited);
+        assert_eq!(limited.y, 100.0);
+
+        // Outside the zone the stick may move freely to get back in
+        let outside = Vec2::new(-50.0, 100.0);
+        assert_eq!(
+            zone.limit_step(outside, Vec2::new(-20.0, 100.0)),
+            Vec2::new(-20.0, 100.0)
+        );
+    }
+}